@@ -0,0 +1,82 @@
+//! Compares batch sampling against evaluating every channel one sample at a
+//! time, and the precalculated curves against exact evaluation. Run with
+//! `cargo bench --bench sample`.
+
+use automaton_rs::{curve::Evaluation, state::SaveState, Time};
+use serde_json::{json, Value};
+use std::{collections::HashMap, hint::black_box, time::Instant};
+
+const CHANNELS: usize = 64;
+const ITEMS: usize = 200;
+const SAMPLES: usize = 10_000;
+const RUNS: usize = 20;
+
+fn project() -> Value {
+    let channels = (0..CHANNELS)
+        .map(|c| {
+            let items = (0..ITEMS)
+                .map(|i| json!({ "time": i as f32 * 0.5, "length": 0.4, "curve": (c + i) % 4 }))
+                .collect::<Vec<_>>();
+            json!([format!("channel{}", c), { "items": items }])
+        })
+        .collect::<Vec<_>>();
+
+    let curves = (0..4)
+        .map(|i| json!({ "nodes": [[0, 0, 0, 0, 0.1, 1], [0.4, i, -0.1, -1]] }))
+        .collect::<Vec<_>>();
+
+    json!({ "resolution": 1000, "curves": curves, "channels": channels })
+}
+
+fn measure(name: &str, mut run: impl FnMut()) {
+    run();
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        run();
+    }
+
+    let elapsed = start.elapsed() / RUNS as u32;
+    let per_sample = elapsed.as_secs_f64() * 1e9 / (CHANNELS * SAMPLES) as f64;
+    println!(
+        "{:<8} {:>10.3?} per run, {:>6.2} ns per sample",
+        name, elapsed, per_sample
+    );
+}
+
+fn main() {
+    let mut state = SaveState::from_json(project(), &HashMap::new()).unwrap();
+    let duration = ITEMS as Time * 0.5;
+    let times = (0..SAMPLES)
+        .map(|i| i as Time / SAMPLES as Time * duration)
+        .collect::<Vec<_>>();
+    let mut out = vec![0.0; CHANNELS * SAMPLES];
+
+    measure("scalar", || {
+        for (channel, out) in state.channels().iter().zip(out.chunks_exact_mut(SAMPLES)) {
+            for (&time, out) in times.iter().zip(out) {
+                *out = channel.get_value(black_box(time));
+            }
+        }
+        black_box(&out);
+    });
+
+    measure("batch", || {
+        state.sample_into(black_box(&times), &mut out);
+        black_box(&out);
+    });
+
+    for (i, curve) in state.curves().iter().enumerate() {
+        let error = curve.precalc_error(16);
+        println!(
+            "curve {}: precalc error {:.2e} max at {:.4}, {:.2e} rms",
+            i, error.max, error.max_time, error.rms
+        );
+    }
+
+    state.set_evaluation(Evaluation::Exact);
+    measure("exact", || {
+        state.sample_into(black_box(&times), &mut out);
+        black_box(&out);
+    });
+}
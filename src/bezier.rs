@@ -1,5 +1,4 @@
-use crate::curve::Node;
-use std::cell::RefCell;
+use crate::{curve::Node, Time};
 
 const NEWTON_ITER: u32 = 4;
 const NEWTON_EPSILON: f32 = 0.001;
@@ -9,9 +8,7 @@ const SUBDIV_EPSILON: f32 = 0.000001;
 
 const TABLE_SIZE: usize = 21;
 
-thread_local! {
-    static CACHE: RefCell<[f32; TABLE_SIZE]> = RefCell::new([0.0; TABLE_SIZE]);
-}
+const DERIVATIVE_MARGIN: f32 = 0.0001;
 
 #[inline]
 fn a(cps: [f32; 4]) -> f32 {
@@ -71,19 +68,26 @@ fn newton(x: f32, mut t: f32, cps: [f32; 4]) -> f32 {
         }
 
         let cx = cubic_bezier(t, cps) - x;
-        t = t - cx / d;
+        t -= cx / d;
     }
 
     t
 }
 
 #[inline]
-pub fn bezier_easing(node0: &Node, node1: &Node, time: f32) -> f32 {
-    let mut cpsx = [
-        node0.time,
-        node0.time + node0.out_time,
-        node1.time + node1.in_time,
-        node1.time,
+fn second_delta_cubic_bezier(t: f32, cps: [f32; 4]) -> f32 {
+    6.0 * a(cps) * t + 2.0 * b(cps)
+}
+
+/// Control points of the segment between two nodes. Times are relative to
+/// the first node, so they keep their precision far into the timeline.
+pub fn control_points(node0: &Node, node1: &Node) -> ([f32; 4], [f32; 4]) {
+    let length = node1.time - node0.time;
+    let mut cpsx: [f32; 4] = [
+        0.0,
+        node0.out_time as _,
+        (length + node1.in_time) as _,
+        length as _,
     ];
 
     let cpsy = [
@@ -93,34 +97,28 @@ pub fn bezier_easing(node0: &Node, node1: &Node, time: f32) -> f32 {
         node1.value,
     ];
 
-    if time <= cpsx[0] {
-        return cpsy[0];
-    }
-
-    if time >= cpsx[3] {
-        return cpsy[3];
-    }
-
     cpsx[1] = cpsx[1].clamp(cpsx[0], cpsx[3]);
     cpsx[2] = cpsx[2].clamp(cpsx[0], cpsx[3]);
 
-    let (sample, dist) = CACHE.with(|cache_ref| {
-        let mut cache = cache_ref.borrow_mut();
+    (cpsx, cpsy)
+}
 
-        for i in 0..TABLE_SIZE {
-            let t = i as f32 / (TABLE_SIZE as f32 - 1.0);
-            cache[i] = cubic_bezier(t, cpsx)
-        }
+// finds the curve parameter of the given time, which must lie between the
+// first and the last control point
+fn solve(time: f32, cpsx: [f32; 4]) -> f32 {
+    let mut table = [0.0; TABLE_SIZE];
+    for (i, x) in table.iter_mut().enumerate() {
+        let t = i as f32 / (TABLE_SIZE as f32 - 1.0);
+        *x = cubic_bezier(t, cpsx);
+    }
 
-        let sample = cache
-            .iter()
-            .skip(1)
-            .position(|&c| c > time)
-            .unwrap_or(TABLE_SIZE - 2);
+    let sample = table
+        .iter()
+        .skip(1)
+        .position(|&x| x > time)
+        .unwrap_or(TABLE_SIZE - 2);
 
-        let dist = (time - cache[sample]) / (cache[sample + 1] - cache[sample]);
-        (sample, dist)
-    });
+    let dist = (time - table[sample]) / (table[sample + 1] - table[sample]);
 
     let mut t = (sample as f32 + dist) / (TABLE_SIZE as f32 - 1.0);
     let d = delta_cubic_bezier(t, cpsx) / (cpsx[3] - cpsx[0]);
@@ -137,9 +135,57 @@ pub fn bezier_easing(node0: &Node, node1: &Node, time: f32) -> f32 {
         );
     }
 
+    t
+}
+
+#[inline]
+pub fn bezier_easing(node0: &Node, node1: &Node, time: Time) -> f32 {
+    let (cpsx, cpsy) = control_points(node0, node1);
+    let time: f32 = (time - node0.time) as _;
+
+    if time <= cpsx[0] {
+        return cpsy[0];
+    }
+
+    if time >= cpsx[3] {
+        return cpsy[3];
+    }
+
+    let t = solve(time, cpsx);
     cubic_bezier(t, cpsy)
 }
 
+/// First and second derivative of the value with respect to time.
+pub fn bezier_easing_derivatives(node0: &Node, node1: &Node, time: Time) -> (f32, f32) {
+    let (cpsx, cpsy) = control_points(node0, node1);
+    let time: f32 = (time - node0.time) as _;
+
+    if cpsx[0] >= cpsx[3] {
+        return (0.0, 0.0);
+    }
+
+    let t = if time <= cpsx[0] {
+        0.0
+    } else if time >= cpsx[3] {
+        1.0
+    } else {
+        solve(time, cpsx)
+    };
+
+    // handles without length make the curve stand still at its ends, where
+    // the slope is only defined as a limit
+    let t = t.clamp(DERIVATIVE_MARGIN, 1.0 - DERIVATIVE_MARGIN);
+
+    let dx = delta_cubic_bezier(t, cpsx);
+    let dy = delta_cubic_bezier(t, cpsy);
+    let ddx = second_delta_cubic_bezier(t, cpsx);
+    let ddy = second_delta_cubic_bezier(t, cpsy);
+
+    let slope = dy / dx;
+    let curvature = (ddy * dx - dy * ddx) / (dx * dx * dx);
+    (slope, curvature)
+}
+
 #[cfg(test)]
 mod test {
     use float_cmp::assert_approx_eq;
@@ -169,4 +215,32 @@ mod test {
         assert_approx_eq!(f32, bezier_easing(n0, n1, 5.0), 2.0, epsilon = 0.01);
         assert_approx_eq!(f32, bezier_easing(n0, n1, 6.0), 2.0);
     }
+
+    #[test]
+    fn derivatives() {
+        let n0 = &Node::new(2.0, 4.0);
+        let n1 = &Node::new(6.0, 2.0);
+
+        for &time in &[2.0, 3.0, 4.5, 6.0] {
+            let (slope, curvature) = bezier_easing_derivatives(n0, n1, time);
+            assert_approx_eq!(f32, slope, -0.5, epsilon = 0.001);
+            assert_approx_eq!(f32, curvature, 0.0, epsilon = 0.01);
+        }
+
+        let n0 = &Node::with_out(2.0, 4.0, 1.0, 1.35);
+        let n1 = &Node::with_in(6.0, 2.0, -1.0, -1.35);
+
+        let h = 0.001;
+        let width: f32 = (2.0 * h) as _;
+        let slope = |t: Time| bezier_easing_derivatives(n0, n1, t).0;
+        for &time in &[2.5, 3.0, 4.0, 5.0, 5.5] {
+            let value = |t: Time| bezier_easing(n0, n1, t);
+            let expected_slope = (value(time + h) - value(time - h)) / width;
+            let expected_curvature = (slope(time + h) - slope(time - h)) / width;
+
+            let (actual_slope, curvature) = bezier_easing_derivatives(n0, n1, time);
+            assert_approx_eq!(f32, actual_slope, expected_slope, epsilon = 0.01);
+            assert_approx_eq!(f32, curvature, expected_curvature, epsilon = 0.01);
+        }
+    }
 }
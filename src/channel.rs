@@ -1,65 +1,894 @@
 use serde_json::Value;
-use std::sync::Arc;
+use std::{cmp::Ordering, fmt::Debug, sync::Arc};
 
-use crate::{curve::Curve, item::Item};
+use crate::{
+    curve::Curve,
+    error::{self, LoadError, SaveError},
+    item::Item,
+    order::FileOrder,
+    query::{self, Crossing, Direction, Extremum},
+    Time,
+};
 
-#[derive(Debug)]
+/// Handle to a channel of a [`SaveState`](crate::state::SaveState).
+///
+/// Looking a channel up by handle is a plain index, so hot loops should
+/// resolve names once and keep the handle around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChannelId(pub(crate) usize);
+
+impl ChannelId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Sent to channel listeners for every item that is active during an update.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelUpdateEvent {
+    /// Time the channel was updated to.
+    pub time: Time,
+    /// Value of the item at this update.
+    pub value: f32,
+    /// Time since the item began, clamped to its length.
+    pub elapsed: Time,
+    /// Beginning time of the item.
+    pub begin: Time,
+    /// End time of the item.
+    pub end: Time,
+    /// Time length of the item.
+    pub length: Time,
+    /// `elapsed / length`, or 1 for items without length.
+    pub progress: f32,
+    /// Whether the item became active with this update.
+    pub init: bool,
+    /// Whether the item became inactive with this update.
+    pub uninit: bool,
+}
+
+/// Listeners only need to be `Send`, since they are only ever called by the
+/// playback that owns their channel.
+pub type ChannelListener = Box<dyn FnMut(&ChannelUpdateEvent) + Send>;
+
+/// The listeners of a channel. They are only called through `&mut`, which
+/// keeps channels `Sync` without asking the same of every listener.
+#[derive(Default)]
+struct Listeners(Vec<ChannelListener>);
+
+// SAFETY: a shared `&Listeners` gives no way to reach the listeners, every
+// call and every change goes through `&mut Listeners`.
+unsafe impl Sync for Listeners {}
+
+/// A named track of items, along with its playback state.
+///
+/// The items are shared between clones, so every [`Automaton`](crate::Automaton)
+/// playing a state gets its own cheap copy of each channel to advance.
+/// Listeners belong to one playback, a clone starts without any.
 pub struct Channel {
-    items: Vec<Item>,
+    /// Sorted by time, stably, so items at the same time keep their order.
+    items: Arc<[Item]>,
+    item_order: Arc<FileOrder>,
     value: f32,
-    time: f32,
+    time: Time,
     head: usize,
+    listeners: Listeners,
+}
+
+impl Clone for Channel {
+    fn clone(&self) -> Self {
+        Self {
+            items: self.items.clone(),
+            item_order: self.item_order.clone(),
+            value: self.value,
+            time: self.time,
+            head: self.head,
+            listeners: Listeners::default(),
+        }
+    }
+}
+
+impl Debug for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Channel")
+            .field("items", &self.items)
+            .field("value", &self.value)
+            .field("time", &self.time)
+            .field("head", &self.head)
+            .field("listeners", &self.listeners.0.len())
+            .finish()
+    }
 }
 
 impl Channel {
-    pub(crate) fn from_json(json: &Value, curves: &[Arc<Curve>]) -> Self {
-        let items = json
-            .as_array()
+    pub(crate) fn from_json(json: &Value, curves: &[Arc<Curve>]) -> Result<Self, LoadError> {
+        error::as_object(json, "")?;
+
+        let items = error::field_array(json, "items")?
             .into_iter()
             .flatten()
-            .map(|item_json| Item::from_json(item_json, curves))
-            .collect();
+            .enumerate()
+            .map(|(i, item_json)| {
+                Item::from_json(item_json, curves).map_err(|e| e.within(&format!("items[{}]", i)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self::new(items))
+    }
+
+    /// Creates a channel from items in any order.
+    pub(crate) fn new(items: Vec<Item>) -> Self {
+        let (items, item_order) = FileOrder::sort(&items, |a, b| {
+            a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal)
+        });
 
         Self {
-            items,
+            items: items.into(),
+            item_order: Arc::new(item_order),
             value: 0.0,
-            time: f32::NEG_INFINITY,
+            time: Time::NEG_INFINITY,
             head: 0,
+            listeners: Listeners::default(),
         }
     }
 
+    pub(crate) fn to_json(&self, curves: &[Arc<Curve>]) -> Result<Value, SaveError> {
+        let items = self
+            .items_in_file_order()
+            .map(|(i, item)| {
+                item.to_json(curves)
+                    .map_err(|e| e.within(&format!("items[{}]", i)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(serde_json::json!({ "items": items }))
+    }
+
+    /// Items of the channel, sorted by time.
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    /// Position of the item with the given index in the file it was loaded
+    /// from.
+    pub(crate) fn item_position(&self, index: usize) -> usize {
+        self.item_order.position(index)
+    }
+
+    /// Items in the order of the file they were loaded from, along with their
+    /// position in it.
+    pub(crate) fn items_in_file_order(&self) -> impl Iterator<Item = (usize, &Item)> {
+        self.item_order.in_file_order(&self.items)
+    }
+
     pub fn current_value(&self) -> f32 {
         self.value
     }
 
-    pub fn current_time(&self) -> f32 {
+    pub fn current_time(&self) -> Time {
         self.time
     }
 
     pub fn reset(&mut self) {
-        self.time = f32::NEG_INFINITY;
+        self.time = Time::NEG_INFINITY;
         self.value = 0.0;
         self.head = 0;
     }
 
-    // TODO: figure out what a listener is
-    //pub fn subscribe(&mut self, listener: ??) {}
+    /// Points items that use one of the old curves at the curve with the same
+    /// index in `new`.
+    pub(crate) fn replace_curves(&mut self, old: &[Arc<Curve>], new: &[Arc<Curve>]) {
+        let mut items = self.items.to_vec();
+        for curve in items.iter_mut().filter_map(|item| item.curve.as_mut()) {
+            if let Some(index) = old.iter().position(|c| Arc::ptr_eq(c, curve)) {
+                *curve = new[index].clone();
+            }
+        }
+
+        self.items = items.into();
+    }
 
-    pub fn get_value(&self, time: f32) -> f32 {
-        let index = self
+    /// Takes over the playback position and the listeners of the same channel
+    /// in an older version of the project, without sending any events.
+    pub(crate) fn resume_from(&mut self, old: &mut Channel) {
+        self.listeners = std::mem::take(&mut old.listeners);
+        self.time = old.time;
+        self.head = self
             .items
             .iter()
-            .position(|item| time < item.time)
-            .unwrap_or(self.items.len() - 1);
+            .take_while(|item| item.end() <= old.time)
+            .count();
+        self.value = self.get_value(old.time);
+    }
 
-        if index == 0 {
-            return 0.0;
+    /// Registers a listener that is called for every item the channel passes
+    /// through during an update.
+    pub fn subscribe(
+        &mut self,
+        listener: impl FnMut(&ChannelUpdateEvent) + Send + 'static,
+    ) {
+        self.listeners.0.push(Box::new(listener));
+    }
+
+    /// Moves the channel to the given time, caches its value there and
+    /// notifies the listeners.
+    ///
+    /// Every item that was active at some point since the last call sends one
+    /// event, so items that are skipped over still get their `init` and
+    /// `uninit` events. The channel remembers the first item it has not
+    /// passed yet, so moving forward only looks at the items in between, and
+    /// moving backward walks that cursor back instead of starting over. Items
+    /// that become active again by going back get a new `init` event.
+    pub fn consume(&mut self, time: Time) {
+        let prev_time = self.time;
+        let rewound = time < prev_time;
+        if rewound {
+            self.rewind(time);
         }
 
-        let item = &self.items[index];
-        let t = (time - item.time).min(item.length);
-        item.get_value(t)
+        for i in self.head..self.items.len() {
+            let item = &self.items[i];
+            let begin = item.time;
+            let length = item.length;
+
+            let mut elapsed = time - begin;
+            if elapsed < 0.0 {
+                break;
+            }
+
+            let progress: f32;
+            let uninit = length <= elapsed;
+            if uninit {
+                elapsed = length;
+                progress = 1.0;
+                if i == self.head {
+                    self.head += 1;
+                }
+            } else {
+                progress = if length != 0.0 { (elapsed / length) as _ } else { 1.0 };
+            }
+
+            self.value = item.get_value(elapsed);
+
+            let event = ChannelUpdateEvent {
+                time,
+                value: self.value,
+                elapsed,
+                begin,
+                end: item.end(),
+                length,
+                progress,
+                init: if rewound {
+                    !(begin <= prev_time && prev_time < item.end())
+                } else {
+                    prev_time < begin
+                },
+                uninit,
+            };
+
+            for listener in &mut self.listeners.0 {
+                listener(&event);
+            }
+        }
+
+        self.time = time;
     }
 
-    // TODO: consume
+    /// Walks the cursor back to the first item that has not ended by the
+    /// given time, and restores the value held after the item before it.
+    fn rewind(&mut self, time: Time) {
+        while self.head > 0 && self.items[self.head - 1].end() > time {
+            self.head -= 1;
+        }
+
+        self.value = match self.head.checked_sub(1) {
+            Some(i) => self.items[i].get_value(self.items[i].length),
+            None => 0.0,
+        };
+    }
+
+    /// Evaluates the channel at the given time without touching its playback
+    /// state.
+    ///
+    /// The last item that has begun by `time` decides the value, so where one
+    /// item ends exactly as the next one begins, the next one wins. Between
+    /// items the value of the previous item is held, or 0 if that item resets.
+    /// Before the first item and on empty channels the value is 0. This always
+    /// agrees with the value [`Channel::consume`] settles on.
+    pub fn get_value(&self, time: Time) -> f32 {
+        let next = self.items.partition_point(|item| item.time <= time);
+        self.value_before(next, time)
+    }
+
+    /// Rate of change of [`Channel::get_value`] at the given time. It is 0
+    /// before the first item and wherever a value is held between items.
+    pub fn get_velocity(&self, time: Time) -> f32 {
+        self.derivatives(time).0
+    }
+
+    /// Rate of change of [`Channel::get_velocity`] at the given time.
+    pub fn get_acceleration(&self, time: Time) -> f32 {
+        self.derivatives(time).1
+    }
+
+    fn derivatives(&self, time: Time) -> (f32, f32) {
+        let next = self.items.partition_point(|item| item.time <= time);
+        match next.checked_sub(1).map(|i| &self.items[i]) {
+            Some(item) if time <= item.end() => item.get_derivatives(time - item.time),
+            _ => (0.0, 0.0),
+        }
+    }
+
+    /// All times in `[t0, t1]` where the value crosses the threshold, in
+    /// order. Jumps between items count as crossings too.
+    pub fn crossings(&self, threshold: f32, t0: Time, t1: Time) -> Vec<Crossing> {
+        let vertices = self.vertices(t0, t1, |cps| query::hull_excludes(cps, threshold));
+        query::crossings(&vertices, threshold)
+    }
+
+    /// Finds the first time after the given one where the value crosses the
+    /// threshold, optionally only in the given direction.
+    pub fn next_crossing(
+        &self,
+        threshold: f32,
+        time: Time,
+        direction: Option<Direction>,
+    ) -> Option<Crossing> {
+        let last_end = self.items.iter().map(Item::end).fold(time, Time::max);
+        let next = self.items.partition_point(|item| item.time <= time);
+
+        // look at one item at a time, so an early hit skips the rest
+        let mut t0 = time;
+        let boundaries = self.items[next..].iter().map(|item| item.time);
+        for t1 in boundaries.chain(std::iter::once(last_end)) {
+            let found = self.crossings(threshold, t0, t1).into_iter().find(|crossing| {
+                crossing.time > time && direction.is_none_or(|d| crossing.direction == d)
+            });
+
+            if found.is_some() {
+                return found;
+            }
+
+            t0 = t1;
+        }
+
+        None
+    }
+
+    /// Local minima and maxima within `[t0, t1]`, in order.
+    pub fn extrema(&self, t0: Time, t1: Time) -> Vec<Extremum> {
+        query::extrema(&self.vertices(t0, t1, query::monotonic))
+    }
+
+    /// Smallest and largest value within `[t0, t1]`.
+    pub fn value_range(&self, t0: Time, t1: Time) -> (f32, f32) {
+        query::value_range(&self.vertices(t0, t1, query::monotonic))
+    }
+
+    /// Corners of the polyline [`Channel::get_value`] follows within
+    /// `[t0, t1]`. Jumps show up as two corners at the same time.
+    fn vertices(&self, t0: Time, t1: Time, skip: impl Fn([f32; 4]) -> bool) -> Vec<(Time, f32)> {
+        let first = self.items.partition_point(|item| item.time <= t0);
+        let mut vertices = Vec::new();
+
+        if first == 0 {
+            let until = self.items.first().map_or(t1, |item| item.time.min(t1));
+            vertices.extend([(t0, 0.0), (until, 0.0)]);
+        }
+
+        for (i, item) in self.items.iter().enumerate().skip(first.saturating_sub(1)) {
+            if item.time > t1 {
+                break;
+            }
+
+            // the item is in charge until the next one begins
+            let from = item.time.max(t0);
+            let until = self.items.get(i + 1).map_or(t1, |next| next.time.min(t1));
+            if from > until || (from == until && until < t1) {
+                continue;
+            }
+
+            let curve_until = until.min(item.end());
+            if from <= curve_until {
+                let elapsed = item.vertices(from - item.time, curve_until - item.time, &skip);
+                vertices.extend(elapsed.into_iter().map(|(e, v)| (item.time + e, v)));
+            }
+
+            if until >= item.end() {
+                let held = item.get_value(item.length);
+                vertices.extend([(item.end().max(from), held), (until, held)]);
+            }
+        }
+
+        vertices
+    }
+
+    /// Evaluates the channel at many times at once, same as calling
+    /// [`Channel::get_value`] for each of them.
+    ///
+    /// Runs of ascending times only walk forward through the items instead of
+    /// searching them again for every sample.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `times` and `out` differ in length.
+    pub fn sample_into(&self, times: &[Time], out: &mut [f32]) {
+        assert_eq!(times.len(), out.len(), "one output per sample time");
+
+        let mut next = 0;
+        let mut prev_time = Time::NEG_INFINITY;
+        for (&time, out) in times.iter().zip(out) {
+            if time >= prev_time {
+                while next < self.items.len() && self.items[next].time <= time {
+                    next += 1;
+                }
+            } else {
+                next = self.items.partition_point(|item| item.time <= time);
+            }
+
+            *out = self.value_before(next, time);
+            prev_time = time;
+        }
+    }
+
+    /// Value at the given time, where `next` is the first item that begins
+    /// after it.
+    fn value_before(&self, next: usize, time: Time) -> f32 {
+        let item = match next.checked_sub(1) {
+            Some(i) => &self.items[i],
+            None => return 0.0,
+        };
+
+        let elapsed = (time - item.time).min(item.length);
+        item.get_value(elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn constant(time: Time, length: Time, value: f32) -> Item {
+        Item {
+            time,
+            length,
+            value,
+            offset: 0.0,
+            speed: 1.0,
+            amp: 1.0,
+            reset: false,
+            curve: None,
+            unresolved_curve: None,
+        }
+    }
+
+    #[test]
+    fn channel_consume() {
+        let mut channel = Channel::new(vec![
+            constant(0.0, 1.0, 1.0),
+            constant(1.0, 1.0, 2.0),
+            constant(2.0, 1.0, 3.0),
+        ]);
+
+        assert_eq!(channel.current_time(), Time::NEG_INFINITY);
+        assert_eq!(channel.current_value(), 0.0);
+
+        channel.consume(1.5);
+        assert_eq!(channel.current_time(), 1.5);
+        assert_eq!(channel.current_value(), 2.0);
+
+        channel.consume(2.5);
+        assert_eq!(channel.current_time(), 2.5);
+        assert_eq!(channel.current_value(), 3.0);
+
+        channel.consume(0.5);
+        assert_eq!(channel.current_time(), 0.5);
+        assert_eq!(channel.current_value(), 1.0);
+
+        channel.reset();
+        assert_eq!(channel.current_time(), Time::NEG_INFINITY);
+        assert_eq!(channel.current_value(), 0.0);
+    }
+
+    fn record(channel: &mut Channel) -> Arc<Mutex<Vec<ChannelUpdateEvent>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        channel.subscribe(move |event| sink.lock().unwrap().push(*event));
+        events
+    }
+
+    #[test]
+    fn channel_listener_events() {
+        let mut channel = Channel::new(vec![constant(1.0, 2.0, 5.0), constant(4.0, 0.0, 7.0)]);
+        let events = record(&mut channel);
+
+        channel.consume(0.5);
+        assert!(events.lock().unwrap().is_empty());
+
+        channel.consume(1.5);
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [ChannelUpdateEvent {
+                time: 1.5,
+                value: 5.0,
+                elapsed: 0.5,
+                begin: 1.0,
+                end: 3.0,
+                length: 2.0,
+                progress: 0.25,
+                init: true,
+                uninit: false,
+            }]
+        );
+
+        channel.consume(2.0);
+        let event = events.lock().unwrap().pop().unwrap();
+        assert!(!event.init && !event.uninit);
+        assert_eq!(event.progress, 0.5);
+
+        // jumping past both items still reports them, the first one ending
+        // and the second one beginning and ending at once
+        channel.consume(5.0);
+        let flags = events
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|e| (e.begin, e.init, e.uninit, e.progress, e.elapsed))
+            .collect::<Vec<_>>();
+        assert_eq!(flags, [(1.0, false, true, 1.0, 2.0), (4.0, true, true, 1.0, 0.0)]);
+        assert_eq!(channel.current_value(), 7.0);
+
+        // passed items are not reported again
+        channel.consume(6.0);
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(channel.current_value(), 7.0);
+
+        // going back re-enters the first item
+        channel.consume(2.0);
+        let event = events.lock().unwrap().pop().unwrap();
+        assert!(event.init && !event.uninit);
+        assert_eq!(channel.current_value(), 5.0);
+    }
+
+    #[test]
+    fn channel_consume_matches_replay() {
+        // a long channel with gaps, touching items and items that reset
+        let items = (0..2000)
+            .map(|i| {
+                let mut item = constant(i as Time * 0.5, 0.25 + (i % 3) as Time * 0.125, i as f32);
+                item.reset = i % 7 == 0;
+                item
+            })
+            .collect::<Vec<_>>();
+
+        let mut channel = Channel::new(items);
+        let mut seed = 12345u32;
+        for _ in 0..500 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let time = (seed >> 8) as Time / (1 << 24) as Time * 1100.0 - 50.0;
+            channel.consume(time);
+
+            let mut fresh = channel.clone();
+            fresh.reset();
+            fresh.consume(time);
+
+            assert_eq!(channel.current_value(), fresh.current_value(), "at {}", time);
+            assert_eq!(channel.head, fresh.head, "at {}", time);
+        }
+    }
+
+    #[test]
+    fn channel_consume_visits_few_items() {
+        let items = (0..10000)
+            .map(|i| constant(i as Time, 1.0, i as f32))
+            .collect::<Vec<_>>();
+
+        let mut channel = Channel::new(items);
+        channel.consume(5000.5);
+
+        let events = record(&mut channel);
+        channel.consume(5000.75);
+        assert_eq!(events.lock().unwrap().len(), 1);
+
+        // going back a little only revisits the items in between
+        events.lock().unwrap().clear();
+        channel.consume(4998.5);
+        let begins = events.lock().unwrap().iter().map(|e| e.begin).collect::<Vec<_>>();
+        assert_eq!(begins, [4998.0]);
+        assert!(events.lock().unwrap()[0].init);
+        assert_eq!(channel.head, 4998);
+        assert_eq!(channel.current_value(), 4998.0);
+    }
+
+    #[test]
+    fn channel_rewind_within_item() {
+        let mut channel = Channel::new(vec![constant(1.0, 2.0, 5.0)]);
+        let events = record(&mut channel);
+
+        channel.consume(2.5);
+        channel.consume(1.5);
+        channel.consume(0.5);
+
+        let flags = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| (e.init, e.uninit))
+            .collect::<Vec<_>>();
+
+        // still the same activation when going back inside the item
+        assert_eq!(flags, [(true, false), (false, false)]);
+        assert_eq!(channel.current_value(), 0.0);
+    }
+
+    #[test]
+    fn channel_get_value() {
+        let mut reset = constant(6.0, 1.0, 4.0);
+        reset.reset = true;
+
+        let channel = Channel::new(vec![
+            constant(1.0, 1.0, 1.0),
+            constant(2.0, 1.0, 2.0),
+            constant(4.0, 0.0, 3.0),
+            reset,
+            constant(8.0, 0.0, 5.0),
+            constant(8.0, 1.0, 6.0),
+        ]);
+
+        let cases = [
+            (-1.0, 0.0),
+            (0.0, 0.0),
+            // first item begins
+            (1.0, 1.0),
+            (1.5, 1.0),
+            // items meet, the later one wins
+            (2.0, 2.0),
+            (2.5, 2.0),
+            // end of an item, followed by a gap
+            (3.0, 2.0),
+            (3.5, 2.0),
+            // zero length item, held afterwards
+            (4.0, 3.0),
+            (5.0, 3.0),
+            // reset item, 0 from its end on
+            (6.0, 4.0),
+            (6.5, 4.0),
+            (7.0, 0.0),
+            (7.5, 0.0),
+            // zero length item and another one at the same time
+            (8.0, 6.0),
+            (9.0, 6.0),
+            (100.0, 6.0),
+        ];
+
+        // the same items out of order, as in a hand-edited file
+        let mut shuffled = channel.items().to_vec();
+        shuffled.rotate_left(3);
+        let unsorted = Channel::new(shuffled);
+
+        for &(time, expected) in &cases {
+            assert_eq!(channel.get_value(time), expected, "at {}", time);
+            assert_eq!(unsorted.get_value(time), expected, "at {}", time);
+
+            let mut playback = channel.clone();
+            playback.consume(time);
+            assert_eq!(playback.current_value(), expected, "at {}", time);
+        }
+    }
+
+    #[test]
+    fn channel_parse_unsorted() {
+        let json = serde_json::json!({
+            "items": [{ "time": 2, "value": 2 }, { "time": 0, "value": 1 }]
+        });
+
+        let mut channel = Channel::from_json(&json, &[]).unwrap();
+        let times = channel.items().iter().map(|item| item.time).collect::<Vec<_>>();
+        assert_eq!(times, [0.0, 2.0]);
+
+        assert_eq!(channel.get_value(0.5), 1.0);
+        assert_eq!(channel.get_value(2.5), 2.0);
+        channel.consume(0.5);
+        assert_eq!(channel.current_value(), 1.0);
+
+        // saved as written
+        let written = channel.to_json(&[]).unwrap();
+        assert_eq!(written["items"][0]["time"], 2);
+    }
+
+    #[test]
+    fn channel_sample_into() {
+        let channel = Channel::new(
+            (0..100)
+                .map(|i| constant(i as Time * 0.5, 0.25, i as f32))
+                .collect(),
+        );
+
+        // ascending, then jumping back, then repeated times and a NaN
+        let times = [
+            -1.0, 0.1, 0.3, 7.7, 7.8, 30.0, 2.2, 2.2, 49.9, Time::NAN, 0.6, 60.0,
+        ];
+        let mut out = [0.0; 12];
+        channel.sample_into(&times, &mut out);
+
+        for (&time, &value) in times.iter().zip(&out) {
+            assert_eq!(value, channel.get_value(time), "at {}", time);
+        }
+    }
+
+    #[test]
+    fn channel_velocity() {
+        use crate::curve::Node;
+
+        let curve = Arc::new(Curve::new(&[
+            Node::new(0.0, 0.0),
+            Node::with_in(1.0, 1.0, -0.5, 0.0),
+        ]));
+
+        let mut item = constant(1.0, 2.0, 0.5);
+        item.curve = Some(curve);
+        item.speed = 0.5;
+        item.amp = 4.0;
+        let channel = Channel::new(vec![item]);
+
+        let h = 0.001;
+        let width: f32 = (2.0 * h) as _;
+        for &time in &[1.5, 2.0, 2.5] {
+            let expected = (channel.get_value(time + h) - channel.get_value(time - h)) / width;
+            assert!((channel.get_velocity(time) - expected).abs() < 0.05, "at {}", time);
+
+            let expected =
+                (channel.get_velocity(time + h) - channel.get_velocity(time - h)) / width;
+            assert!((channel.get_acceleration(time) - expected).abs() < 0.05, "at {}", time);
+        }
+
+        assert_eq!(channel.get_velocity(0.5), 0.0);
+        assert_eq!(channel.get_velocity(3.5), 0.0);
+        assert_eq!(Channel::new(Vec::new()).get_acceleration(1.0), 0.0);
+    }
+
+    #[test]
+    fn channel_queries() {
+        use crate::{curve::Node, query::ExtremumKind};
+
+        let mut ramp = constant(1.0, 1.0, 0.0);
+        ramp.curve = Some(Arc::new(Curve::new(&[
+            Node::new(0.0, 0.0),
+            Node::new(1.0, 1.0),
+        ])));
+        ramp.amp = 2.0;
+
+        let mut flash = constant(5.0, 1.0, 3.0);
+        flash.reset = true;
+
+        let channel = Channel::new(vec![ramp, constant(3.0, 1.0, 0.0), flash]);
+
+        let crossings = channel.crossings(1.0, 0.0, 7.0);
+        let directions = crossings.iter().map(|c| c.direction).collect::<Vec<_>>();
+        assert_eq!(
+            directions,
+            [
+                Direction::Rising,
+                Direction::Falling,
+                Direction::Rising,
+                Direction::Falling
+            ]
+        );
+        assert!((crossings[0].time - 1.5).abs() < 0.02);
+        assert_eq!(crossings[1..].iter().map(|c| c.time).collect::<Vec<_>>(), [3.0, 5.0, 6.0]);
+
+        let next = |time, direction| channel.next_crossing(1.0, time, direction).map(|c| c.time);
+        assert!((next(0.0, Some(Direction::Rising)).unwrap() - 1.5).abs() < 0.02);
+        assert_eq!(next(1.6, Some(Direction::Rising)), Some(5.0));
+        assert_eq!(next(1.6, Some(Direction::Falling)), Some(3.0));
+        assert_eq!(next(5.0, None), Some(6.0));
+        assert_eq!(next(6.0, None), None);
+
+        let extrema = channel
+            .extrema(0.0, 7.0)
+            .into_iter()
+            .map(|e| (e.time, e.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            extrema,
+            [
+                (2.0, ExtremumKind::Maximum),
+                (3.0, ExtremumKind::Minimum),
+                (5.0, ExtremumKind::Maximum)
+            ]
+        );
+
+        assert_eq!(channel.value_range(0.0, 7.0), (0.0, 3.0));
+        assert_eq!(channel.value_range(3.5, 4.5), (0.0, 0.0));
+        assert!(Channel::new(Vec::new()).crossings(0.5, 0.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn channel_get_value_empty() {
+        let channel = Channel::new(Vec::new());
+        assert_eq!(channel.get_value(-1.0), 0.0);
+        assert_eq!(channel.get_value(0.0), 0.0);
+        assert_eq!(channel.get_value(1.0), 0.0);
+    }
+
+    #[test]
+    fn channel_listener_not_sync() {
+        let mut channel = Channel::new(vec![constant(0.0, 1.0, 1.0), constant(2.0, 1.0, 2.0)]);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let count = std::cell::Cell::new(0);
+        channel.subscribe(move |event| {
+            count.set(count.get() + 1);
+            sender.send((count.get(), event.value)).unwrap();
+        });
+
+        channel.consume(2.5);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [(1, 1.0), (2, 2.0)]);
+    }
+
+    #[test]
+    fn channel_clone_drops_listeners() {
+        let mut channel = Channel::new(vec![constant(0.0, 1.0, 1.0)]);
+        let events = record(&mut channel);
+
+        let mut clone = channel.clone();
+        clone.consume(0.5);
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(clone.current_value(), 1.0);
+
+        channel.consume(0.5);
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn channel_consume_empty() {
+        let mut channel = Channel::new(Vec::new());
+        channel.consume(1.0);
+        assert_eq!(channel.current_value(), 0.0);
+    }
+
+    #[cfg(feature = "f64-time")]
+    #[test]
+    fn channel_boundaries_after_long_playback() {
+        use crate::curve::Node;
+
+        // f32 times are 0.0625 apart at this point, which would merge all
+        // three items into one
+        let start = 1e6;
+        let mut ramp = constant(start + 0.002, 1.0, 0.0);
+        ramp.curve = Some(Arc::new(Curve::new(&[
+            Node::new(0.0, 0.0),
+            Node::new(1.0, 1.0),
+        ])));
+
+        let mut channel = Channel::new(vec![
+            constant(start, 0.001, 1.0),
+            constant(start + 0.001, 0.001, 2.0),
+            ramp,
+        ]);
+        let events = record(&mut channel);
+
+        let frames = [
+            (start + 0.0005, 1.0),
+            (start + 0.001, 2.0),
+            (start + 0.0015, 2.0),
+            (start + 0.252, 0.25),
+            (start + 0.502, 0.5),
+        ];
+        for (time, expected) in frames {
+            channel.consume(time);
+            assert!((channel.current_value() - expected).abs() < 0.01, "at {}", time);
+            assert!((channel.get_value(time) - expected).abs() < 0.01, "at {}", time);
+        }
+
+        // elapsed times are off by rounding only, far below a microsecond
+        let events = events.lock().unwrap();
+        let inits = events.iter().filter(|e| e.init).collect::<Vec<_>>();
+        let expected = [(start, 0.0005), (start + 0.001, 0.0), (start + 0.002, 0.25)];
+        assert_eq!(inits.len(), expected.len());
+        for (event, (begin, elapsed)) in inits.iter().zip(expected) {
+            assert_eq!(event.begin, begin);
+            assert!((event.elapsed - elapsed).abs() < 1e-9, "{:?}", event);
+        }
+    }
 }
@@ -0,0 +1,388 @@
+//! Upgrades project data saved by older editor releases to the current layout.
+//!
+//! Every step only knows about the layout right before and right after it, so
+//! old projects are walked through all steps in order, like upstream's compat
+//! layer does.
+
+use crate::error::{self, LoadError};
+use serde_json::{json, Map, Value};
+
+/// Major version of the layout `SaveState` reads.
+pub(crate) const CURRENT_VERSION: u64 = 4;
+
+/// Version string written into migrated projects.
+pub(crate) const CURRENT_VERSION_STRING: &str = "4.0.0";
+
+pub(crate) fn migrate(mut json: Value) -> Result<Value, LoadError> {
+    error::as_object(&json, "")?;
+
+    let version = detect_version(&json)?;
+    if version > CURRENT_VERSION {
+        return Err(LoadError::schema(
+            "version",
+            format!("unsupported project version {}", version),
+        ));
+    }
+
+    if version < 3 {
+        json = v2_to_v3(json)?;
+    }
+
+    if version < 4 {
+        json = v3_to_v4(json)?;
+    }
+
+    Ok(json)
+}
+
+/// Reads the major version from the `version` field, or guesses it from the
+/// layout for projects that predate it.
+fn detect_version(json: &Value) -> Result<u64, LoadError> {
+    if let Some(version) = error::field(json, "version", Value::as_str, "a string")? {
+        return version
+            .split('.')
+            .next()
+            .and_then(|major| major.trim().parse().ok())
+            .ok_or_else(|| LoadError::schema("version", "expected a version like `4.0.0`"));
+    }
+
+    if json.get("params").is_some() {
+        return Ok(2);
+    }
+
+    match json.get("channels") {
+        Some(Value::Object(channels)) => {
+            let flat = channels
+                .values()
+                .any(|channel| channel.get("nodes").is_some());
+            Ok(if flat { 2 } else { 3 })
+        }
+        _ => Ok(CURRENT_VERSION),
+    }
+}
+
+/// Before v3 every channel was a single curve stored under its name, either
+/// in `params` or `channels`. Since v3 curves live in their own list and
+/// channels reference them through items.
+fn v2_to_v3(mut json: Value) -> Result<Value, LoadError> {
+    let root = json.as_object_mut().unwrap();
+    let key = if root.contains_key("params") {
+        "params"
+    } else {
+        "channels"
+    };
+
+    let old_channels = match root.remove(key) {
+        None | Some(Value::Null) => Map::new(),
+        Some(Value::Object(channels)) => channels,
+        Some(_) => return Err(LoadError::schema(key, "expected an object")),
+    };
+
+    let mut curves = Vec::new();
+    let mut channels = Map::new();
+
+    for (name, channel) in old_channels {
+        let path = format!("{}.{}", key, name);
+        error::as_object(&channel, &path)?;
+
+        let nodes = error::field_array(&channel, "nodes")
+            .map_err(|e| e.within(&path))?
+            .cloned()
+            .unwrap_or_default();
+
+        let length = match nodes.last() {
+            Some(node) => error::field_f64(node, "time")
+                .map_err(|e| e.within(&format!("{}.nodes[{}]", path, nodes.len() - 1)))?
+                .unwrap_or(0.0),
+            None => 0.0,
+        };
+
+        let fxs = channel.get("fxs").cloned().unwrap_or_else(|| json!([]));
+
+        channels.insert(
+            name,
+            json!({
+                "items": [{
+                    "time": 0.0,
+                    "length": length,
+                    "curve": curves.len(),
+                }],
+            }),
+        );
+        curves.push(json!({ "nodes": nodes, "fxs": fxs }));
+    }
+
+    root.remove("length");
+    root.insert("curves".to_string(), Value::Array(curves));
+    root.insert("channels".to_string(), Value::Object(channels));
+    root.insert("version".to_string(), json!("3.0.0"));
+
+    Ok(json)
+}
+
+/// v4 stores channels as `[name, channel]` pairs so their order is kept, and
+/// nodes as flat `[time, value, inTime, inValue, outTime, outValue]` arrays.
+fn v3_to_v4(mut json: Value) -> Result<Value, LoadError> {
+    let root = json.as_object_mut().unwrap();
+
+    if let Some(curves) = root.get_mut("curves").and_then(Value::as_array_mut) {
+        for (i_curve, curve) in curves.iter_mut().enumerate() {
+            let nodes = match curve.get_mut("nodes").and_then(Value::as_array_mut) {
+                Some(nodes) => nodes,
+                None => continue,
+            };
+
+            for (i_node, node) in nodes.iter_mut().enumerate() {
+                if node.is_object() {
+                    *node = v3_node_to_v4(node)
+                        .map_err(|e| e.within(&format!("curves[{}].nodes[{}]", i_curve, i_node)))?;
+                }
+            }
+        }
+    }
+
+    let channels = match root.remove("channels") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Object(channels)) => channels
+            .into_iter()
+            .map(|(name, channel)| json!([name, channel]))
+            .collect(),
+        Some(_) => return Err(LoadError::schema("channels", "expected an object")),
+    };
+
+    root.insert("channels".to_string(), Value::Array(channels));
+    root.insert("version".to_string(), json!(CURRENT_VERSION_STRING));
+
+    Ok(json)
+}
+
+fn v3_node_to_v4(node: &Value) -> Result<Value, LoadError> {
+    let handle = |key: &str, field: &str| -> Result<f64, LoadError> {
+        match node.get(key) {
+            Some(handle) => error::field_f64(handle, field)
+                .map(Option::unwrap_or_default)
+                .map_err(|e| e.within(key)),
+            None => Ok(0.0),
+        }
+    };
+
+    Ok(json!([
+        error::field_f64(node, "time")?.unwrap_or(0.0),
+        error::field_f64(node, "value")?.unwrap_or(0.0),
+        handle("in", "time")?,
+        handle("in", "value")?,
+        handle("out", "time")?,
+        handle("out", "value")?,
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V2_FIXTURE: &str = r#"{
+        "version": "2.0.0",
+        "length": 2.0,
+        "resolution": 100,
+        "channels": {
+            "cameraX": {
+                "nodes": [
+                    { "time": 0.0, "value": 0.0, "out": { "time": 0.5, "value": 0.0 } },
+                    { "time": 2.0, "value": 1.0, "in": { "time": -0.5, "value": 0.0 } }
+                ],
+                "fxs": []
+            }
+        }
+    }"#;
+
+    const V3_FIXTURE: &str = r#"{
+        "version": "3.1.0",
+        "resolution": 100,
+        "curves": [
+            {
+                "nodes": [
+                    { "time": 0.0, "value": 0.0, "out": { "time": 0.5, "value": 0.0 } },
+                    { "time": 2.0, "value": 1.0, "in": { "time": -0.5, "value": 0.0 } }
+                ],
+                "fxs": []
+            }
+        ],
+        "channels": {
+            "cameraX": {
+                "items": [{ "time": 0.0, "length": 2.0, "curve": 0 }]
+            }
+        },
+        "labels": { "intro": 0.0 }
+    }"#;
+
+    /// Channel names in the order the editor listed them, not alphabetically.
+    const UNORDERED_FIXTURE: &str = r#"{
+        "version": "3.0.0",
+        "curves": [],
+        "channels": {
+            "zoom": { "items": [] },
+            "cameraX": { "items": [] },
+            "fade": { "items": [] }
+        }
+    }"#;
+
+    fn parse(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn detect_versions() {
+        assert_eq!(detect_version(&parse(V2_FIXTURE)).unwrap(), 2);
+        assert_eq!(detect_version(&parse(V3_FIXTURE)).unwrap(), 3);
+        assert_eq!(
+            detect_version(&parse(r#"{ "version": "4.2.1" }"#)).unwrap(),
+            4
+        );
+
+        assert_eq!(detect_version(&parse(r#"{ "params": {} }"#)).unwrap(), 2);
+        assert_eq!(
+            detect_version(&parse(r#"{ "channels": { "a": { "nodes": [] } } }"#)).unwrap(),
+            2
+        );
+        assert_eq!(
+            detect_version(&parse(r#"{ "channels": { "a": { "items": [] } } }"#)).unwrap(),
+            3
+        );
+        assert_eq!(detect_version(&parse(r#"{ "channels": [] }"#)).unwrap(), 4);
+
+        assert!(detect_version(&parse(r#"{ "version": "next" }"#)).is_err());
+    }
+
+    #[test]
+    fn migrate_v2_to_v3() {
+        let actual = v2_to_v3(parse(V2_FIXTURE)).unwrap();
+        let expected = parse(
+            r#"{
+                "version": "3.0.0",
+                "resolution": 100,
+                "curves": [
+                    {
+                        "nodes": [
+                            { "time": 0.0, "value": 0.0, "out": { "time": 0.5, "value": 0.0 } },
+                            { "time": 2.0, "value": 1.0, "in": { "time": -0.5, "value": 0.0 } }
+                        ],
+                        "fxs": []
+                    }
+                ],
+                "channels": {
+                    "cameraX": {
+                        "items": [{ "time": 0.0, "length": 2.0, "curve": 0 }]
+                    }
+                }
+            }"#,
+        );
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn migrate_v2_params() {
+        let json = r#"{
+            "rev": 20190221,
+            "params": { "fade": { "nodes": [{ "time": 0 }, { "time": 1.5, "value": 1 }] } }
+        }"#;
+
+        let actual = migrate(parse(json)).unwrap();
+        assert_eq!(
+            actual["curves"][0]["nodes"],
+            parse("[[0.0, 0.0, 0.0, 0.0, 0.0, 0.0], [1.5, 1.0, 0.0, 0.0, 0.0, 0.0]]")
+        );
+        assert_eq!(actual["channels"][0][0], "fade");
+        assert_eq!(actual["channels"][0][1]["items"][0]["length"], 1.5);
+        assert!(actual.get("params").is_none());
+    }
+
+    #[test]
+    fn migrate_v3_to_v4() {
+        let actual = v3_to_v4(parse(V3_FIXTURE)).unwrap();
+        let expected = parse(
+            r#"{
+                "version": "4.0.0",
+                "resolution": 100,
+                "curves": [
+                    {
+                        "nodes": [
+                            [0.0, 0.0, 0.0, 0.0, 0.5, 0.0],
+                            [2.0, 1.0, -0.5, 0.0, 0.0, 0.0]
+                        ],
+                        "fxs": []
+                    }
+                ],
+                "channels": [
+                    ["cameraX", { "items": [{ "time": 0.0, "length": 2.0, "curve": 0 }] }]
+                ],
+                "labels": { "intro": 0.0 }
+            }"#,
+        );
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn migrate_keeps_channel_order() {
+        let names = |json: &Value| {
+            json["channels"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|pair| pair[0].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let actual = v3_to_v4(parse(UNORDERED_FIXTURE)).unwrap();
+        assert_eq!(names(&actual), ["zoom", "cameraX", "fade"]);
+
+        let json = r#"{
+            "version": "2.0.0",
+            "channels": {
+                "zoom": { "nodes": [{ "time": 0 }, { "time": 1 }] },
+                "cameraX": { "nodes": [{ "time": 0 }, { "time": 2 }] }
+            }
+        }"#;
+        let actual = migrate(parse(json)).unwrap();
+        assert_eq!(names(&actual), ["zoom", "cameraX"]);
+        assert_eq!(actual["channels"][1][1]["items"][0]["curve"], 1);
+    }
+
+    #[test]
+    fn migrate_all_steps() {
+        let from_v2 = migrate(parse(V2_FIXTURE)).unwrap();
+        let from_v3 = migrate(parse(V3_FIXTURE)).unwrap();
+
+        assert_eq!(from_v2["curves"], from_v3["curves"]);
+        assert_eq!(from_v2["channels"], from_v3["channels"]);
+        assert_eq!(from_v2["version"], CURRENT_VERSION_STRING);
+    }
+
+    #[test]
+    fn migrate_current_is_untouched() {
+        let json = parse(
+            r#"{
+                "version": "4.1.0",
+                "curves": [{ "nodes": [[0, 1], [1]] }],
+                "channels": [["a", { "items": [] }]]
+            }"#,
+        );
+
+        assert_eq!(migrate(json.clone()).unwrap(), json);
+    }
+
+    #[test]
+    fn migrate_errors() {
+        let err = migrate(parse(r#"{ "version": "5.0.0" }"#)).unwrap_err();
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "version"));
+
+        let json = r#"{ "version": "3.0.0", "curves": [{ "nodes": [{ "time": "0" }] }] }"#;
+        let err = migrate(parse(json)).unwrap_err();
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "curves[0].nodes[0].time"));
+
+        let json = r#"{ "version": "2.0.0", "channels": { "x": { "nodes": 1 } } }"#;
+        let err = migrate(parse(json)).unwrap_err();
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "channels.x.nodes"));
+    }
+}
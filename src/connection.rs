@@ -3,27 +3,28 @@ use tungstenite::WebSocket;
 
 #[derive(Debug)]
 pub struct Connection {
+    #[allow(dead_code)]
     inner: WebSocket<TcpStream>,
 }
 
 impl Connection {
-    pub fn new() -> Self {
-        let (inner, _) = tungstenite::connect("ws://localhost:12250/").unwrap();
+    /// Connects to the editor running on this machine.
+    pub fn new() -> Result<Self, Box<tungstenite::Error>> {
+        let (inner, _) = tungstenite::connect("ws://localhost:12250/").map_err(Box::new)?;
 
-        Self { inner }
+        Ok(Self { inner })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    #[cfg(disabled)]
+    use super::*;
+
     #[test]
+    #[ignore = "requires a running editor"]
     fn websocket_test() {
-        let (mut ws, _) = tungstenite::connect("ws://localhost:12250/").unwrap();
-
-        loop {
-            let msg = ws.read_message().unwrap();
-            println!("{}", msg);
-        }
+        let mut connection = Connection::new().unwrap();
+        let msg = connection.inner.read_message().unwrap();
+        assert!(!msg.is_close());
     }
 }
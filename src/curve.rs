@@ -1,38 +1,71 @@
-use std::collections::HashMap;
+use std::{cell::Cell, cmp::Ordering, collections::HashMap};
 
 use serde_json::Value;
 
-use crate::{bezier::bezier_easing, fx::{FxContext, FxFnBoxFn, FxSection}};
+use crate::{
+    bezier::{bezier_easing, bezier_easing_derivatives, control_points},
+    error::{self, LoadError, SaveError},
+    fx::{FxContext, FxFnBoxFn, FxSection},
+    grid::{self, Grid},
+    json,
+    order::FileOrder,
+    query::{self, Crossing, Extremum},
+    Time,
+};
 
 #[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, rename_all = "camelCase")
+)]
 pub struct Node {
-    pub time: f32,
+    pub time: Time,
     pub value: f32,
-    pub in_time: f32,
+    pub in_time: Time,
     pub in_value: f32,
-    pub out_time: f32,
+    pub out_time: Time,
     pub out_value: f32,
 }
 
 impl Node {
-    pub(crate) fn from_json(json: &Value) -> Self {
-        let mut iter = json
-            .as_array()
-            .into_iter()
-            .flatten()
-            .flat_map(Value::as_f64);
+    pub(crate) fn from_json(json: &Value) -> Result<Self, LoadError> {
+        let mut iter = error::as_array(json, "")?
+            .iter()
+            .enumerate()
+            .map(|(i, v)| error::as_f64(v, &format!("[{}]", i)))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
 
-        Self {
+        Ok(Self {
             time: iter.next().unwrap_or(0.0) as _,
             value: iter.next().unwrap_or(0.0) as _,
             in_time: iter.next().unwrap_or(0.0) as _,
             in_value: iter.next().unwrap_or(0.0) as _,
             out_time: iter.next().unwrap_or(0.0) as _,
             out_value: iter.next().unwrap_or(0.0) as _,
+        })
+    }
+
+    /// Writes the node as an array, leaving out trailing zeros like the editor.
+    pub(crate) fn to_json(&self) -> Result<Value, SaveError> {
+        let mut fields = vec![
+            json::time(self.time, "[0]")?,
+            json::number(self.value, "[1]")?,
+            json::time(self.in_time, "[2]")?,
+            json::number(self.in_value, "[3]")?,
+            json::time(self.out_time, "[4]")?,
+            json::number(self.out_value, "[5]")?,
+        ];
+
+        while fields.len() > 2 && fields.last() == Some(&Value::from(0)) {
+            fields.pop();
         }
+
+        Ok(Value::Array(fields))
     }
 
-    pub fn new(time: f32, value: f32) -> Self {
+    pub fn new(time: Time, value: f32) -> Self {
         Self {
             time,
             value,
@@ -40,7 +73,7 @@ impl Node {
         }
     }
 
-    pub fn with_in(time: f32, value: f32, in_time: f32, in_value: f32) -> Self {
+    pub fn with_in(time: Time, value: f32, in_time: Time, in_value: f32) -> Self {
         Self {
             time,
             value,
@@ -50,7 +83,7 @@ impl Node {
         }
     }
 
-    pub fn with_out(time: f32, value: f32, out_time: f32, out_value: f32) -> Self {
+    pub fn with_out(time: Time, value: f32, out_time: Time, out_value: f32) -> Self {
         Self {
             time,
             value,
@@ -61,90 +94,485 @@ impl Node {
     }
 }
 
+/// How [`Curve::get_value`] finds the value at a given time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Evaluation {
+    /// Interpolates linearly between the precalculated values, like the
+    /// editor does.
+    #[default]
+    Precalc,
+    /// Solves the bezier segments at the exact time, so fast curves do not
+    /// alias and closely spaced nodes keep their shape. Fx sections can only
+    /// be evaluated at the precalculated values, so times within one sample
+    /// of an fx section still use those.
+    Exact,
+}
+
+/// Largest and average difference between exact and precalculated values,
+/// see [`Curve::precalc_error`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PrecalcError {
+    /// Largest absolute difference.
+    pub max: f32,
+    /// Time of the largest difference.
+    pub max_time: Time,
+    /// Root mean square of the differences.
+    pub rms: f32,
+}
+
+/// Curves serialize together with their precalculated values, so they can be
+/// deserialized without the fx definitions they were built with.
 #[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "CurveRepr", try_from = "CurveRepr")
+)]
 pub struct Curve {
-    pub nodes: Vec<Node>,
-    pub fxs: Vec<FxSection>,
+    /// Sorted by time, and never fewer than 2. Changed through the editing
+    /// methods, e.g. [`Curve::move_node`], which keep the precalculated values
+    /// up to date.
+    nodes: Vec<Node>,
+    /// Sorted in the order they are applied, by row and then by time.
+    fxs: Vec<FxSection>,
+    node_order: FileOrder,
+    fx_order: FileOrder,
+    grid: Grid,
+    values: Vec<f32>,
+    /// Set by fx for values that hold until the next one instead of being
+    /// interpolated, see [`FxContext::set_should_not_interpolate`].
+    should_not_interpolate: Vec<bool>,
+    evaluation: Evaluation,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "Curve")]
+struct CurveRepr {
+    nodes: Vec<Node>,
+    fxs: Vec<FxSection>,
+    resolution: usize,
     values: Vec<f32>,
+    #[serde(default)]
+    should_not_interpolate: Vec<bool>,
+    #[serde(default)]
+    evaluation: Evaluation,
+}
+
+/// Nodes and fx sections are written in file order, like [`Curve::to_json`].
+#[cfg(feature = "serde")]
+impl From<Curve> for CurveRepr {
+    fn from(curve: Curve) -> Self {
+        let nodes = curve.node_order.in_file_order(&curve.nodes);
+        let fxs = curve.fx_order.in_file_order(&curve.fxs);
+
+        Self {
+            nodes: nodes.map(|(_, node)| node.clone()).collect(),
+            fxs: fxs.map(|(_, fx)| fx.clone()).collect(),
+            resolution: curve.grid.resolution(),
+            values: curve.values,
+            should_not_interpolate: curve.should_not_interpolate,
+            evaluation: curve.evaluation,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<CurveRepr> for Curve {
+    type Error = String;
+
+    fn try_from(repr: CurveRepr) -> Result<Self, Self::Error> {
+        if repr.nodes.len() < 2 {
+            return Err(format!(
+                "a curve must consist of at least 2 nodes, got {}",
+                repr.nodes.len()
+            ));
+        }
+
+        let (nodes, node_order) = FileOrder::sort(&repr.nodes, by_time);
+        let (fxs, fx_order) = FileOrder::sort(&repr.fxs, by_row_and_time);
+
+        let length = nodes.last().map_or(0.0, |n| n.time);
+        let grid = Grid::new(repr.resolution, length);
+        if repr.resolution == 0 || repr.values.len() != grid.len() {
+            return Err("a curve must have precalculated values".to_string());
+        }
+
+        let mut should_not_interpolate = repr.should_not_interpolate;
+        if should_not_interpolate.is_empty() {
+            should_not_interpolate = vec![false; grid.len()];
+        } else if should_not_interpolate.len() != grid.len() {
+            return Err("a curve must have one interpolation flag per value".to_string());
+        }
+
+        Ok(Self {
+            nodes,
+            fxs,
+            node_order,
+            fx_order,
+            grid,
+            values: repr.values,
+            should_not_interpolate,
+            evaluation: repr.evaluation,
+        })
+    }
 }
 
 impl Curve {
-    pub(crate) fn from_json(json: &Value, resolution: usize, fxs: &HashMap<String, FxFnBoxFn>) -> Self {
-        let nodes = json
-            .get("nodes")
-            .and_then(Value::as_array)
+    pub(crate) fn from_json(
+        json: &Value,
+        resolution: usize,
+        fxs: &HashMap<String, FxFnBoxFn>,
+    ) -> Result<Self, LoadError> {
+        error::as_object(json, "")?;
+
+        let nodes = error::field_array(json, "nodes")?
             .into_iter()
             .flatten()
-            .map(Node::from_json)
-            .collect::<Vec<_>>();
+            .enumerate()
+            .map(|(i, v)| Node::from_json(v).map_err(|e| e.within(&format!("nodes[{}]", i))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sections = error::field_array(json, "fxs")?
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(i, v)| FxSection::from_json(v).map_err(|e| e.within(&format!("fxs[{}]", i))))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        Self::with_params(&nodes, resolution, fxs)
+        Self::with_params(&nodes, &sections, resolution, fxs)
+    }
+
+    /// Writes the curve, with nodes and fx sections in the order of the file
+    /// they were loaded from.
+    pub(crate) fn to_json(&self) -> Result<Value, SaveError> {
+        let nodes = self
+            .node_order
+            .in_file_order(&self.nodes)
+            .map(|(i, node)| {
+                node.to_json()
+                    .map_err(|e| e.within(&format!("nodes[{}]", i)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let fxs = self
+            .fx_order
+            .in_file_order(&self.fxs)
+            .map(|(i, fx)| fx.to_json().map_err(|e| e.within(&format!("fxs[{}]", i))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(serde_json::json!({ "nodes": nodes, "fxs": fxs }))
     }
 
     #[cfg(test)]
     pub(crate) fn new(nodes: &[Node]) -> Self {
-        Self::with_params(nodes, 100, &HashMap::new())
+        Self::with_params(nodes, &[], 100, &HashMap::new()).unwrap()
     }
 
-    pub(crate) fn with_params(nodes: &[Node], resolution: usize, fxs: &HashMap<String, FxFnBoxFn>) -> Self {
+    pub(crate) fn with_params(
+        nodes: &[Node],
+        sections: &[FxSection],
+        resolution: usize,
+        fxs: &HashMap<String, FxFnBoxFn>,
+    ) -> Result<Self, LoadError> {
         if nodes.len() < 2 {
-            panic!(
-                "A curve must consist of at least 2 nodes, got {}",
-                nodes.len()
-            );
+            return Err(LoadError::schema(
+                "nodes",
+                format!(
+                    "a curve must consist of at least 2 nodes, got {}",
+                    nodes.len()
+                ),
+            ));
+        }
+
+        let (nodes, node_order) = FileOrder::sort(nodes, by_time);
+        let length = nodes[nodes.len() - 1].time;
+        if !Grid::fits(resolution, length) {
+            return Err(LoadError::schema(
+                format!("nodes[{}]", node_order.position(nodes.len() - 1)),
+                format!(
+                    "a curve of length {} needs more than {} values at resolution {}",
+                    length,
+                    grid::MAX_LEN,
+                    resolution
+                ),
+            ));
         }
 
+        let (sections, fx_order) = FileOrder::sort(sections, by_row_and_time);
         let mut this = Self {
-            nodes: nodes.to_vec(),
+            nodes,
+            fxs: sections,
+            node_order,
+            fx_order,
+            grid: Grid::default(),
             values: Vec::new(),
-            fxs: Vec::new(),
+            should_not_interpolate: Vec::new(),
+            evaluation: Evaluation::Precalc,
         };
 
         this.precalc(resolution, fxs);
-        this
+        Ok(this)
     }
 
     fn precalc(&mut self, resolution: usize, fxs: &HashMap<String, FxFnBoxFn>) {
-        self.generate_curve(resolution);
-        self.apply_fxs(resolution, fxs);
+        self.grid = Grid::new(resolution, self.length());
+        self.recompute(Time::NEG_INFINITY, Time::INFINITY, fxs);
+    }
+
+    /// Nodes of the curve, sorted by time.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Fx sections of the curve, in the order they are applied.
+    pub fn fxs(&self) -> &[FxSection] {
+        &self.fxs
+    }
+
+    /// Position of the node with the given index in the file it was loaded
+    /// from.
+    pub(crate) fn node_position(&self, index: usize) -> usize {
+        self.node_order.position(index)
+    }
+
+    /// Position of the fx section with the given index in the file it was
+    /// loaded from.
+    pub(crate) fn fx_position(&self, index: usize) -> usize {
+        self.fx_order.position(index)
+    }
+
+    /// Inserts a node after the nodes at the same time or before it, and
+    /// returns its index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the curve gets too long to precalculate, see
+    /// [`SaveState::from_json`](crate::state::SaveState::from_json).
+    pub fn insert_node(&mut self, node: Node, fxs: &HashMap<String, FxFnBoxFn>) -> usize {
+        let index = self.nodes.partition_point(|n| n.time <= node.time);
+        self.nodes.insert(index, node);
+        self.node_order.insert(index);
+
+        let (t0, t1) = self.node_range(index);
+        self.recompute(t0, t1, fxs);
+        index
     }
 
-    fn generate_curve(&mut self, resolution: usize) {
-        self.nodes.sort_unstable_by(|a, b| {
-            a.time
-                .partial_cmp(&b.time)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
+    /// Removes a node, unless it is one of the last two.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no node with this index.
+    pub fn remove_node(&mut self, index: usize, fxs: &HashMap<String, FxFnBoxFn>) -> Option<Node> {
+        assert!(index < self.nodes.len(), "no node with index {}", index);
+        if self.nodes.len() <= 2 {
+            return None;
+        }
+
+        let (t0, t1) = self.node_range(index);
+        let node = self.nodes.remove(index);
+        self.node_order.remove(index);
+        self.recompute(t0, t1, fxs);
+        Some(node)
+    }
 
-        let values_length = (resolution as f32 * self.length()).ceil() as usize + 1;
-        self.values = Vec::with_capacity(values_length);
-        unsafe { self.values.set_len(values_length) };
+    /// Moves a node to another time and value, and returns its new index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no node with this index, or if the curve gets too
+    /// long to precalculate.
+    pub fn move_node(
+        &mut self,
+        index: usize,
+        time: Time,
+        value: f32,
+        fxs: &HashMap<String, FxFnBoxFn>,
+    ) -> usize {
+        assert!(index < self.nodes.len(), "no node with index {}", index);
+        let old = self.node_range(index);
 
-        let mut node_tail = self.nodes.first().unwrap();
-        let mut i_tail = 0;
-        for i_node in 0..(self.nodes.len() - 1) {
-            let node0 = node_tail;
-            node_tail = &self.nodes[i_node + 1];
+        let mut node = self.nodes.remove(index);
+        node.time = time;
+        node.value = value;
+        let new_index = self.nodes.partition_point(|n| n.time <= node.time);
+        self.nodes.insert(new_index, node);
+        self.node_order.moved(index, new_index);
 
-            let i0 = i_tail;
-            i_tail = (node_tail.time * resolution as f32).floor() as _;
+        self.recompute_both(old, self.node_range(new_index), fxs);
+        new_index
+    }
+
+    /// Changes the handles of a node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no node with this index.
+    pub fn set_node_handles(
+        &mut self,
+        index: usize,
+        (in_time, in_value): (Time, f32),
+        (out_time, out_value): (Time, f32),
+        fxs: &HashMap<String, FxFnBoxFn>,
+    ) {
+        assert!(index < self.nodes.len(), "no node with index {}", index);
+        let node = &mut self.nodes[index];
+        node.in_time = in_time;
+        node.in_value = in_value;
+        node.out_time = out_time;
+        node.out_value = out_value;
+
+        let (t0, t1) = self.node_range(index);
+        self.recompute(t0, t1, fxs);
+    }
 
-            self.values[i0] = node0.value;
+    /// Adds an fx section after the sections it is applied after, and
+    /// returns its index.
+    pub fn add_fx(&mut self, section: FxSection, fxs: &HashMap<String, FxFnBoxFn>) -> usize {
+        let index = self.fx_index(section.row, section.time);
+        let (t0, t1) = (section.time, section.time + section.length);
+        self.fxs.insert(index, section);
+        self.fx_order.insert(index);
+
+        self.recompute(t0, t1, fxs);
+        index
+    }
+
+    /// Removes an fx section.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no fx section with this index.
+    pub fn remove_fx(&mut self, index: usize, fxs: &HashMap<String, FxFnBoxFn>) -> FxSection {
+        assert!(index < self.fxs.len(), "no fx section with index {}", index);
+        let section = self.fxs.remove(index);
+        self.fx_order.remove(index);
+        self.recompute(section.time, section.time + section.length, fxs);
+        section
+    }
+
+    /// Moves an fx section to another time and row, and returns its new
+    /// index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no fx section with this index.
+    pub fn move_fx(
+        &mut self,
+        index: usize,
+        time: Time,
+        row: usize,
+        fxs: &HashMap<String, FxFnBoxFn>,
+    ) -> usize {
+        assert!(index < self.fxs.len(), "no fx section with index {}", index);
+        let mut section = self.fxs.remove(index);
+        let old = (section.time, section.time + section.length);
+
+        section.time = time;
+        section.row = row;
+        let new_index = self.fx_index(row, time);
+        let new = (time, time + section.length);
+        self.fxs.insert(new_index, section);
+        self.fx_order.moved(index, new_index);
+
+        self.recompute_both(old, new, fxs);
+        new_index
+    }
 
-            for i in (i0 + 1)..=i_tail {
-                let time = i as f32 / resolution as f32;
-                let value = bezier_easing(node0, node_tail, time);
-                self.values[i] = value;
+    /// Times between which the values depend on the given node.
+    fn node_range(&self, index: usize) -> (Time, Time) {
+        let t0 = match index {
+            0 => Time::NEG_INFINITY,
+            _ => self.nodes[index - 1].time,
+        };
+        let t1 = match self.nodes.get(index + 1) {
+            Some(next) => next.time,
+            None => Time::INFINITY,
+        };
+        (t0, t1)
+    }
+
+    /// Index at which an fx section is applied among the others.
+    fn fx_index(&self, row: usize, time: Time) -> usize {
+        self.fxs
+            .partition_point(|fx| fx.row < row || (fx.row == row && fx.time <= time))
+    }
+
+    /// Recomputes two time ranges, without the time between them unless
+    /// they overlap.
+    fn recompute_both(
+        &mut self,
+        (a0, a1): (Time, Time),
+        (b0, b1): (Time, Time),
+        fxs: &HashMap<String, FxFnBoxFn>,
+    ) {
+        if a1 < b0 || b1 < a0 {
+            self.recompute(a0, a1, fxs);
+            self.recompute(b0, b1, fxs);
+        } else {
+            self.recompute(a0.min(b0), a1.max(b1), fxs);
+        }
+    }
+
+    /// Brings the values between the given times up to date with the nodes
+    /// and fx sections.
+    ///
+    /// Fx sections are always applied as a whole, so the recomputed range
+    /// grows over every section that overlaps it. Fx that read values
+    /// outside their own section see those as they were already.
+    fn recompute(&mut self, t0: Time, t1: Time, fxs: &HashMap<String, FxFnBoxFn>) {
+        self.grid = Grid::new(self.grid.resolution(), self.length());
+        let last = self.grid.len() - 1;
+        self.values.resize(last + 1, 0.0);
+        self.should_not_interpolate.resize(last + 1, false);
+
+        let mut range = (
+            self.grid.floor_index(t0),
+            self.grid.ceil_index(t1).min(last),
+        );
+        loop {
+            let grown = self
+                .fx_ranges()
+                .filter(|&(i0, i1)| i0 < i1 && i0 <= range.1 && range.0 <= i1)
+                .fold(range, |(lo, hi), (i0, i1)| (lo.min(i0), hi.max(i1)));
+
+            if grown == range {
+                break;
             }
+            range = grown;
         }
 
-        for i in (i_tail + 1)..self.values.len() {
-            self.values[i] = node_tail.value;
+        for i in range.0..=range.1 {
+            self.values[i] = self.solve(self.grid.time(i));
+            self.should_not_interpolate[i] = false;
         }
+
+        self.apply_fxs(range, fxs);
+    }
+
+    /// Indices of the first and last value each active fx section replaces.
+    fn fx_ranges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let grid = self.grid;
+        self.fxs.iter().filter(|fx| !fx.bypass).map(move |fx| {
+            let i0 = grid.ceil_index(fx.time);
+            let i1 = grid.floor_index(fx.time + fx.length);
+            (i0, i1)
+        })
     }
 
-    fn apply_fxs(&mut self, resolution: usize, fxs: &HashMap<String, FxFnBoxFn>) {
-        for fx in &self.fxs {
+    /// Applies the fx sections that overlap the given range of values.
+    fn apply_fxs(&mut self, (lo, hi): (usize, usize), fxs: &HashMap<String, FxFnBoxFn>) {
+        let grid = self.grid;
+        let resolution = grid.resolution();
+        for fx in self.fxs.iter().filter(|fx| !fx.bypass) {
+            let i0 = grid.ceil_index(fx.time);
+            let i1 = grid.floor_index(fx.time + fx.length);
+            if i0 > hi || i1 < lo {
+                continue;
+            }
+
             let fx_def = fxs.get(&fx.def);
             let mut fx_fn = match fx_def {
                 Some(fx_def) => fx_def(),
@@ -154,20 +582,20 @@ impl Curve {
                 }
             };
 
-            let available_end = f32::min(self.length(), fx.time + fx.length);
-            let i0 = f32::ceil(resolution as f32 * fx.time) as usize;
-            let i1 = f32::floor(resolution as f32 * available_end) as usize;
             if i1 <= i0 {
                 eprintln!("Length of the fx section is being negative");
                 continue;
             }
 
             let temp_length = i1 - i0 + 1;
+            let mut temp_values = Vec::with_capacity(temp_length);
+            let mut temp_flags = Vec::with_capacity(temp_length);
             for i in 0..temp_length {
                 let index = i + i0;
-                let time = index as f32 / resolution as f32;
+                let time = grid.time(index);
                 let elapsed = time - fx.time;
-                let progress = elapsed / fx.length;
+                let progress = (elapsed / fx.length) as _;
+                let flag = Cell::new(self.should_not_interpolate[index]);
 
                 let context = FxContext {
                     index,
@@ -176,31 +604,46 @@ impl Curve {
                     time,
                     t0: fx.time,
                     t1: fx.time + fx.length,
-                    delta_time: 1.0 / resolution as f32,
+                    delta_time: 1.0 / resolution as Time,
                     value: self.values[i + i0],
                     progress,
                     elapsed,
                     resolution,
                     length: fx.length,
-                    //params: fx.params,
+                    params: &fx.params,
                     array: &self.values,
-                    //shouldNotInterpolate: this.__shouldNotInterpolate[ i0 ] === 1,
-                    //setShouldNotInterpolate: ( shouldNotInterpolate: boolean ) => {
-                    //  this.__shouldNotInterpolate[ context.index ] = shouldNotInterpolate ? 1 : 0;
-                    //},
-                    get_value: &|t: f32| self.get_value(t),
+                    should_not_interpolate: flag.get(),
+                    set_should_not_interpolate: &|value| flag.set(value),
+                    get_value: &|t: Time| self.get_value(t),
                     init: i == 0,
                     //state: FxParams::new(),
                 };
 
-                //context.shouldNotInterpolate = this.__shouldNotInterpolate[ i + i0 ] == 1;
-
-                self.values[i] = fx_fn(context);
+                temp_values.push(fx_fn(context));
+                temp_flags.push(flag.get());
             }
+
+            self.values[i0..=i1].copy_from_slice(&temp_values);
+            self.should_not_interpolate[i0..=i1].copy_from_slice(&temp_flags);
+        }
+    }
+
+    /// Value at the given time, as selected by the curve's [`Evaluation`].
+    pub fn get_value(&self, time: Time) -> f32 {
+        match self.evaluation {
+            Evaluation::Exact if !self.near_fx(time) => self.solve(time),
+            _ => self.interpolate(time),
         }
     }
 
-    pub fn get_value(&self, time: f32) -> f32 {
+    /// Value of the bezier segment at the given time, ignoring fx sections.
+    fn solve(&self, time: Time) -> f32 {
+        let (node0, node1) = self.segment(time);
+        bezier_easing(node0, node1, time)
+    }
+
+    /// Value between the precalculated values around the given time.
+    fn interpolate(&self, time: Time) -> f32 {
         if time < 0.0 {
             return *self.values.first().unwrap();
         }
@@ -210,31 +653,244 @@ impl Curve {
             return *self.values.last().unwrap();
         }
 
-        let last = self.values.len() - 2;
-        let index = last as f32 * time / length;
-        let index_i = index.floor() as usize;
-        let index_f = index.fract();
+        let (i, fract) = self.grid.position(time);
+        let v0 = self.values[i];
+        if self.should_not_interpolate[i] {
+            return v0;
+        }
 
-        let v0 = self.values[index_i];
-        let v1 = self.values[index_i + 1];
-        v0 + (v1 - v0) * index_f
+        let v1 = self.values[i + 1];
+        v0 + (v1 - v0) * fract
     }
 
-    pub fn length(&self) -> f32 {
+    /// Rate of change of the value at the given time.
+    ///
+    /// Comes straight from the bezier segments, except near fx sections,
+    /// where it is estimated from the precalculated values.
+    pub fn get_derivative(&self, time: Time) -> f32 {
+        self.derivatives(time).0
+    }
+
+    /// Rate of change of [`Curve::get_derivative`] at the given time.
+    pub fn get_second_derivative(&self, time: Time) -> f32 {
+        self.derivatives(time).1
+    }
+
+    fn derivatives(&self, time: Time) -> (f32, f32) {
+        let length = self.length();
+        if !(0.0..=length).contains(&time) {
+            return (0.0, 0.0);
+        }
+
+        if self.near_fx(time) {
+            let h = 1.0 / self.grid.resolution() as Time;
+            let v0 = self.get_value(time - h);
+            let v1 = self.get_value(time);
+            let v2 = self.get_value(time + h);
+            let h: f32 = h as _;
+            return ((v2 - v0) / (2.0 * h), (v2 - 2.0 * v1 + v0) / (h * h));
+        }
+
+        let (node0, node1) = self.segment(time);
+        bezier_easing_derivatives(node0, node1, time)
+    }
+
+    /// Nodes of the bezier segment at the given time, or the first or last
+    /// segment outside of the curve.
+    fn segment(&self, time: Time) -> (&Node, &Node) {
+        let next = self.nodes.partition_point(|node| node.time <= time);
+        let i = next.clamp(1, self.nodes.len() - 1);
+        (&self.nodes[i - 1], &self.nodes[i])
+    }
+
+    /// Whether the precalculated values around the given time may have been
+    /// changed by an fx section.
+    fn near_fx(&self, time: Time) -> bool {
+        let h = 1.0 / self.grid.resolution() as Time;
+        self.fxs
+            .iter()
+            .any(|fx| !fx.bypass && time + h > fx.time && time - h < fx.time + fx.length)
+    }
+
+    pub fn evaluation(&self) -> Evaluation {
+        self.evaluation
+    }
+
+    pub fn set_evaluation(&mut self, evaluation: Evaluation) {
+        self.evaluation = evaluation;
+    }
+
+    /// Compares the precalculated values against the exact bezier segments,
+    /// at the given number of points per precalculated value.
+    ///
+    /// Times near fx sections are left out, they evaluate the same either
+    /// way.
+    pub fn precalc_error(&self, oversampling: usize) -> PrecalcError {
+        let count = (self.values.len() - 1) * oversampling.max(1);
+        if count == 0 {
+            return PrecalcError::default();
+        }
+
+        let step = self.length() / count as Time;
+
+        let mut error = PrecalcError::default();
+        let mut sum = 0.0;
+        let mut samples = 0;
+        for i in 0..=count {
+            let time = i as Time * step;
+            if self.near_fx(time) {
+                continue;
+            }
+
+            let diff = (self.solve(time) - self.interpolate(time)).abs();
+            if diff > error.max {
+                error.max = diff;
+                error.max_time = time;
+            }
+
+            sum += diff * diff;
+            samples += 1;
+        }
+
+        if samples > 0 {
+            error.rms = (sum / samples as f32).sqrt();
+        }
+
+        error
+    }
+
+    /// All times in `[t0, t1]` where the value crosses the threshold, in
+    /// order.
+    pub fn crossings(&self, threshold: f32, t0: Time, t1: Time) -> Vec<Crossing> {
+        let vertices = self.vertices(t0, t1, |cps| query::hull_excludes(cps, threshold));
+        query::crossings(&vertices, threshold)
+    }
+
+    /// Local minima and maxima within `[t0, t1]`, in order.
+    pub fn extrema(&self, t0: Time, t1: Time) -> Vec<Extremum> {
+        query::extrema(&self.vertices(t0, t1, query::monotonic))
+    }
+
+    /// Smallest and largest value within `[t0, t1]`.
+    pub fn value_range(&self, t0: Time, t1: Time) -> (f32, f32) {
+        query::value_range(&self.vertices(t0, t1, query::monotonic))
+    }
+
+    /// Corners of the polyline [`Curve::get_value`] follows within `[t0, t1]`.
+    ///
+    /// Always the polyline through the precalculated values, which
+    /// [`Evaluation::Exact`] only approximates.
+    ///
+    /// Samples inside a bezier segment are left out if `skip` returns true for
+    /// its control values, and no fx section touches it.
+    pub(crate) fn vertices(
+        &self,
+        t0: Time,
+        t1: Time,
+        skip: impl Fn([f32; 4]) -> bool,
+    ) -> Vec<(Time, f32)> {
+        let mut vertices = vec![(t0, self.get_value(t0))];
+        if t0.partial_cmp(&t1) != Some(std::cmp::Ordering::Less) {
+            return vertices;
+        }
+
+        let grid = self.grid;
+        let first = match grid.floor_index(t0) {
+            i if grid.time(i) <= t0 => i + 1,
+            i => i,
+        };
+        let end = grid.floor_index(t1);
+
+        if grid.time(end) <= t1 {
+            let skipped = self.skippable_samples(skip);
+            let mut skipped = skipped.iter().peekable();
+
+            let mut i = first;
+            while i <= end {
+                // samples that are not interpolated hold until the next one
+                if i > 0 && self.should_not_interpolate[i - 1] {
+                    vertices.push((grid.time(i), self.values[i - 1]));
+                }
+                vertices.push((grid.time(i), self.values[i]));
+
+                while skipped.peek().is_some_and(|&&(_, hi)| hi <= i) {
+                    skipped.next();
+                }
+
+                i = match skipped.peek() {
+                    Some(&&(lo, hi)) if lo <= i => hi.min(end).max(i + 1),
+                    _ => i + 1,
+                };
+            }
+        }
+
+        vertices.push((t1, self.get_value(t1)));
+        vertices
+    }
+
+    /// Ranges of precalculated values that belong to a bezier segment, which
+    /// can be skipped down to their first and last value.
+    fn skippable_samples(&self, skip: impl Fn([f32; 4]) -> bool) -> Vec<(usize, usize)> {
+        let grid = self.grid;
+        let fx_ranges = self
+            .fxs
+            .iter()
+            .filter(|fx| !fx.bypass)
+            .map(|fx| {
+                let i0 = grid.ceil_index(fx.time);
+                let i1 = grid.floor_index(fx.time + fx.length);
+                (i0, i1)
+            })
+            .collect::<Vec<_>>();
+
+        let mut lo = 0;
+        self.nodes
+            .windows(2)
+            .filter_map(|segment| {
+                let hi = grid.floor_index(segment[1].time);
+                let range = (lo, hi);
+                lo = hi;
+
+                let (_, cps) = control_points(&segment[0], &segment[1]);
+                let touched = fx_ranges.iter().any(|&(i0, i1)| i0 <= hi && range.0 <= i1);
+                (range.0 < hi && !touched && skip(cps)).then_some(range)
+            })
+            .collect()
+    }
+
+    /// Number of precalculated values per second.
+    pub fn resolution(&self) -> usize {
+        self.grid.resolution()
+    }
+
+    pub fn length(&self) -> Time {
         self.nodes.last().map(|n| n.time).unwrap_or(0.0)
     }
 }
 
+fn by_time(a: &Node, b: &Node) -> Ordering {
+    a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal)
+}
+
+/// Sections are applied row by row, and from left to right within a row.
+fn by_row_and_time(a: &FxSection, b: &FxSection) -> Ordering {
+    a.row
+        .cmp(&b.row)
+        .then(a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fx::FxFn;
     use float_cmp::assert_approx_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn node_parse_full() {
         let json = "[5.2, 0, -3, 1.0, -2.9, 2]";
         let value = serde_json::from_str(json).unwrap();
-        let node = Node::from_json(&value);
+        let node = Node::from_json(&value).unwrap();
 
         assert_eq!(node.time, 5.2);
         assert_eq!(node.value, 0.0);
@@ -248,7 +904,7 @@ mod tests {
     fn node_parse_empty() {
         let json = "[]";
         let value = serde_json::from_str(json).unwrap();
-        let node = Node::from_json(&value);
+        let node = Node::from_json(&value).unwrap();
 
         assert_eq!(node.time, 0.0);
         assert_eq!(node.value, 0.0);
@@ -268,7 +924,7 @@ mod tests {
         }"#;
 
         let value = serde_json::from_str(json).unwrap();
-        let curve = Curve::from_json(&value, 100, &HashMap::new());
+        let curve = Curve::from_json(&value, 100, &HashMap::new()).unwrap();
 
         assert_eq!(curve.nodes[0].time, 0.0);
         assert_eq!(curve.nodes[0].value, 1.0);
@@ -287,6 +943,130 @@ mod tests {
         assert_eq!(curve.nodes.len(), 2);
     }
 
+    #[test]
+    fn node_to_json() {
+        let node = Node::with_in(1.5, 2.0, -0.25, 0.0);
+        assert_eq!(node.to_json().unwrap().to_string(), "[1.5,2,-0.25]");
+
+        let node = Node::new(0.0, 0.0);
+        assert_eq!(node.to_json().unwrap().to_string(), "[0,0]");
+    }
+
+    #[test]
+    fn node_parse_invalid() {
+        let value = serde_json::from_str(r#"[0, "one"]"#).unwrap();
+        let err = Node::from_json(&value).unwrap_err();
+
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "[1]"));
+    }
+
+    #[test]
+    fn curve_parse_too_short() {
+        let value = serde_json::from_str(r#"{ "nodes": [[0, 1]] }"#).unwrap();
+        let err = Curve::from_json(&value, 100, &HashMap::new()).unwrap_err();
+
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "nodes"));
+    }
+
+    fn fx_add() -> Box<dyn FxFn> {
+        Box::new(|context: FxContext| {
+            context.value + context.params.get_f64("value").unwrap_or(0.0) as f32
+        })
+    }
+
+    fn fx_double() -> Box<dyn FxFn> {
+        Box::new(|context: FxContext| 2.0 * context.value)
+    }
+
+    fn fx_hold() -> Box<dyn FxFn> {
+        Box::new(|context: FxContext| {
+            (context.set_should_not_interpolate)(true);
+            context.value
+        })
+    }
+
+    fn fx_defs() -> HashMap<String, FxFnBoxFn> {
+        let mut fxs = HashMap::new();
+        fxs.insert("add".to_string(), fx_add as FxFnBoxFn);
+        fxs.insert("hold".to_string(), fx_hold as FxFnBoxFn);
+        fxs.insert("double".to_string(), fx_double as FxFnBoxFn);
+        fxs
+    }
+
+    #[test]
+    fn curve_parse_fxs() {
+        let json = r#"{
+            "nodes": [[0, 1], [4, 1]],
+            "fxs": [
+                { "def": "add", "params": { "value": 2 }, "time": 1, "length": 2 },
+                { "def": "add", "params": { "value": 5 }, "time": 0, "length": 4, "bypass": true }
+            ]
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let curve = Curve::from_json(&value, 100, &fx_defs()).unwrap();
+
+        assert_eq!(curve.fxs.len(), 2);
+        assert_approx_eq!(f32, curve.get_value(0.5), 1.0);
+        assert_approx_eq!(f32, curve.get_value(1.5), 3.0);
+        assert_approx_eq!(f32, curve.get_value(2.5), 3.0);
+        assert_approx_eq!(f32, curve.get_value(3.5), 1.0);
+    }
+
+    #[test]
+    fn curve_fxs_row_order() {
+        // the later row is applied on top of the earlier one, regardless of
+        // the order in the file
+        let json = r#"{
+            "nodes": [[0, 1], [2, 1]],
+            "fxs": [
+                { "def": "double", "time": 0, "length": 2, "row": 1 },
+                { "def": "add", "params": { "value": 1 }, "time": 0, "length": 2, "row": 0 }
+            ]
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let curve = Curve::from_json(&value, 100, &fx_defs()).unwrap();
+
+        assert_eq!(curve.fxs[0].row, 0);
+        assert_approx_eq!(f32, curve.get_value(1.0), 4.0);
+    }
+
+    #[test]
+    fn curve_should_not_interpolate() {
+        let sections = [FxSection {
+            time: 0.2,
+            length: 0.4,
+            row: 0,
+            def: "hold".to_string(),
+            params: Default::default(),
+            bypass: false,
+        }];
+        let nodes = [Node::new(0.0, 0.0), Node::new(1.0, 1.0)];
+        let mut curve = Curve::with_params(&nodes, &sections, 10, &fx_defs()).unwrap();
+
+        assert_approx_eq!(f32, curve.get_value(0.15), 0.15);
+        assert_approx_eq!(f32, curve.get_value(0.25), 0.2);
+        assert_approx_eq!(f32, curve.get_value(0.65), 0.6);
+        assert_approx_eq!(f32, curve.get_value(0.75), 0.75);
+
+        // the steps are vertical edges for queries
+        let crossings = curve.crossings(0.25, 0.0, 1.0);
+        assert_eq!(crossings.len(), 1);
+        assert_approx_eq!(Time, crossings[0].time, 0.3);
+        assert_eq!(curve.value_range(0.2, 0.35), (0.2, 0.3));
+
+        curve.set_evaluation(Evaluation::Exact);
+        assert_approx_eq!(f32, curve.get_value(0.25), 0.2);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&curve).unwrap();
+            let curve: Curve = serde_json::from_str(&json).unwrap();
+            assert_approx_eq!(f32, curve.get_value(0.25), 0.2);
+        }
+    }
+
     #[test]
     fn curve_line() {
         let n0 = Node::new(0.0, 0.0);
@@ -312,4 +1092,340 @@ mod tests {
         assert_approx_eq!(f32, curve.get_value(2.0), 0.0);
         assert_approx_eq!(f32, curve.get_value(3.0), 0.0);
     }
+
+    #[test]
+    fn curve_exact_evaluation() {
+        // a spike that falls between two precalculated values
+        let mut curve = Curve::new(&[
+            Node::new(0.0, 0.0),
+            Node::new(0.015, 1.0),
+            Node::new(0.03, 0.0),
+            Node::new(1.0, 0.0),
+        ]);
+        assert_eq!(curve.evaluation(), Evaluation::Precalc);
+        assert!(curve.get_value(0.015) < 0.9);
+
+        curve.set_evaluation(Evaluation::Exact);
+        assert_eq!(curve.get_value(0.015), 1.0);
+        assert_eq!(curve.get_value(-1.0), 0.0);
+        assert_eq!(curve.get_value(2.0), 0.0);
+
+        let error = curve.precalc_error(8);
+        assert!(error.max > 0.1, "{:?}", error);
+        assert!((error.max_time - 0.015).abs() < 0.002, "{:?}", error);
+        assert!(error.rms > 0.0 && error.rms < error.max, "{:?}", error);
+
+        // slow curves are resolved well by the precalculated values
+        let slow = Curve::new(&[
+            Node::with_out(0.0, 0.0, 1.0, 0.0),
+            Node::with_in(2.0, 1.0, -1.0, 0.0),
+        ]);
+        assert!(slow.precalc_error(8).max < 0.01);
+
+        // fx sections still come from the precalculated values
+        let sections = [FxSection {
+            time: 1.5,
+            length: 1.0,
+            row: 0,
+            def: "double".to_string(),
+            params: Default::default(),
+            bypass: false,
+        }];
+        let nodes = [Node::new(0.0, 0.0), Node::new(4.0, 4.0)];
+        let mut curve = Curve::with_params(&nodes, &sections, 100, &fx_defs()).unwrap();
+        curve.set_evaluation(Evaluation::Exact);
+        assert_approx_eq!(f32, curve.get_value(1.0), 1.0);
+        assert_approx_eq!(f32, curve.get_value(2.0), 4.0, epsilon = 0.05);
+        assert_approx_eq!(f32, curve.get_value(3.0), 3.0);
+        assert!(curve.precalc_error(4).max < 1e-5);
+    }
+
+    /// Random curves with node times between the precalculated values.
+    fn random_curves(count: usize) -> Vec<(Vec<Node>, usize)> {
+        let mut seed = 4321u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+
+        (0..count)
+            .map(|_| {
+                let resolution = 1 + (random() * 240.0) as usize;
+                let mut time = 0.0;
+                let nodes = (0..2 + (random() * 5.0) as usize)
+                    .map(|i| {
+                        if i > 0 {
+                            time += Time::from(random() * 1.5);
+                        }
+                        Node {
+                            time,
+                            value: random() * 4.0 - 2.0,
+                            in_time: -Time::from(random()),
+                            in_value: random() - 0.5,
+                            out_time: Time::from(random()),
+                            out_value: random() - 0.5,
+                        }
+                    })
+                    .collect();
+                (nodes, resolution)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn curve_precalc_grid() {
+        let sections = [FxSection {
+            time: 0.3,
+            length: 0.5,
+            row: 0,
+            def: "add".to_string(),
+            params: crate::fx::FxParams::from_json(&serde_json::json!({ "value": 1 })).unwrap(),
+            bypass: false,
+        }];
+
+        for (nodes, resolution) in random_curves(200) {
+            for sections in [&[][..], &sections[..]] {
+                let mut curve =
+                    Curve::with_params(&nodes, sections, resolution, &fx_defs()).unwrap();
+                let grid = curve.grid;
+                assert_eq!(curve.values.len(), grid.len());
+                assert_eq!(grid.time(grid.len() - 1), curve.length());
+
+                // every precalculated value is found at its own time
+                for (i, &value) in curve.values.iter().enumerate() {
+                    let time = grid.time(i);
+                    assert_eq!(curve.get_value(time), value, "{} at {}", i, resolution);
+                }
+
+                // and values in between stay between their neighbors
+                for pair in curve.values.windows(2).take(50).enumerate() {
+                    let (i, values) = pair;
+                    let time = (grid.time(i) + grid.time(i + 1)) / 2.0;
+                    let (lo, hi) = (values[0].min(values[1]), values[0].max(values[1]));
+                    let value = curve.get_value(time);
+                    assert!(lo <= value && value <= hi, "{} at {}", time, resolution);
+                }
+
+                // the precalculated values are exact bezier values
+                curve.set_evaluation(Evaluation::Exact);
+                for (i, &value) in curve.values.iter().enumerate() {
+                    assert_eq!(
+                        curve.get_value(grid.time(i)),
+                        value,
+                        "{} at {}",
+                        i,
+                        resolution
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn curve_edits() {
+        let section = |time, length, row, def: &str| FxSection {
+            time,
+            length,
+            row,
+            def: def.to_string(),
+            params: crate::fx::FxParams::from_json(&serde_json::json!({ "value": 1 })).unwrap(),
+            bypass: false,
+        };
+
+        let mut seed = 1234u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+
+        let defs = fx_defs();
+        for (nodes, resolution) in random_curves(50) {
+            let sections = [section(0.3, 0.5, 0, "add"), section(0.5, 1.0, 1, "double")];
+            let mut curve = Curve::with_params(&nodes, &sections, resolution, &defs).unwrap();
+
+            for _ in 0..20 {
+                let time = Time::from(random() * 8.0);
+                let node = (random() * curve.nodes.len() as f32) as usize;
+                let fx = (random() * curve.fxs.len() as f32) as usize;
+                match (random() * 7.0) as usize {
+                    0 => {
+                        let index = curve.insert_node(Node::new(time, random()), &defs);
+                        assert_eq!(curve.nodes[index].time, time);
+                    }
+                    1 => {
+                        let len = curve.nodes.len();
+                        let removed = curve.remove_node(node, &defs);
+                        assert_eq!(removed.is_some(), len > 2);
+                    }
+                    2 => {
+                        let index = curve.move_node(node, time, random(), &defs);
+                        assert_eq!(curve.nodes[index].time, time);
+                    }
+                    3 => {
+                        let handles = (-Time::from(random()), random(), Time::from(random()), 0.0);
+                        curve.set_node_handles(
+                            node,
+                            (handles.0, handles.1),
+                            (handles.2, handles.3),
+                            &defs,
+                        );
+                    }
+                    4 => {
+                        let def = ["add", "double", "hold"][(random() * 3.0) as usize];
+                        let row = (random() * 3.0) as usize;
+                        let index = curve.add_fx(section(time, 0.4, row, def), &defs);
+                        assert_eq!(curve.fxs[index].time, time);
+                    }
+                    5 if !curve.fxs.is_empty() => {
+                        curve.remove_fx(fx, &defs);
+                    }
+                    6 if !curve.fxs.is_empty() => {
+                        let row = (random() * 3.0) as usize;
+                        let index = curve.move_fx(fx, time, row, &defs);
+                        assert_eq!((curve.fxs[index].time, curve.fxs[index].row), (time, row));
+                    }
+                    _ => continue,
+                }
+
+                // the edited curve is the one it would be loaded as
+                let loaded =
+                    Curve::with_params(&curve.nodes, &curve.fxs, resolution, &defs).unwrap();
+                assert_eq!(curve.grid, loaded.grid);
+                assert_eq!(curve.values, loaded.values);
+                assert_eq!(curve.should_not_interpolate, loaded.should_not_interpolate);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "no node with index 2")]
+    fn curve_edit_missing_node() {
+        let mut curve = Curve::new(&[Node::new(0.0, 0.0), Node::new(1.0, 1.0)]);
+        curve.set_node_handles(2, (-0.5, 0.0), (0.5, 0.0), &HashMap::new());
+    }
+
+    #[test]
+    fn curve_edits_stay_local() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        fn fx_count() -> Box<dyn FxFn> {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            Box::new(|context: FxContext| context.value)
+        }
+
+        let mut defs = fx_defs();
+        defs.insert("count".to_string(), fx_count as FxFnBoxFn);
+
+        let nodes = (0..=10)
+            .map(|i| Node::new(i as Time, 0.0))
+            .collect::<Vec<_>>();
+        let sections = [FxSection {
+            time: 8.0,
+            length: 1.0,
+            row: 0,
+            def: "count".to_string(),
+            params: Default::default(),
+            bypass: false,
+        }];
+        let mut curve = Curve::with_params(&nodes, &sections, 10, &defs).unwrap();
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+
+        let index = curve.insert_node(Node::new(2.5, 1.0), &defs);
+        assert_eq!(index, 3);
+        curve.set_node_handles(index, (-0.2, 0.0), (0.2, 0.0), &defs);
+        let removed = curve.remove_node(index, &defs).unwrap();
+        assert_eq!((removed.time, removed.out_time), (2.5, 0.2));
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+
+        // edits next to the section apply it again
+        curve.move_node(8, 8.5, 1.0, &defs);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+        curve.move_fx(0, 4.0, 0, &defs);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn curve_derivatives() {
+        let curve = Curve::new(&[
+            Node::new(0.0, 0.0),
+            Node::new(1.0, 2.0),
+            Node::with_in(3.0, 0.0, -0.5, 0.0),
+        ]);
+
+        assert_approx_eq!(f32, curve.get_derivative(0.5), 2.0, epsilon = 0.001);
+        assert_approx_eq!(f32, curve.get_second_derivative(0.5), 0.0, epsilon = 0.01);
+        assert_approx_eq!(f32, curve.get_derivative(3.0), 0.0, epsilon = 0.01);
+        assert!(curve.get_derivative(2.0) < 0.0);
+        assert_eq!(curve.get_derivative(-1.0), 0.0);
+        assert_eq!(curve.get_derivative(4.0), 0.0);
+
+        // fx sections are differentiated numerically
+        let mut sections = [FxSection {
+            time: 1.5,
+            length: 1.0,
+            row: 0,
+            def: "double".to_string(),
+            params: Default::default(),
+            bypass: false,
+        }];
+        let nodes = [Node::new(0.0, 0.0), Node::new(4.0, 4.0)];
+
+        let curve = Curve::with_params(&nodes, &sections, 100, &fx_defs()).unwrap();
+        assert_approx_eq!(f32, curve.get_derivative(1.0), 1.0, epsilon = 0.001);
+        assert_approx_eq!(f32, curve.get_derivative(2.0), 2.0, epsilon = 0.05);
+
+        sections[0].bypass = true;
+        let curve = Curve::with_params(&nodes, &sections, 100, &fx_defs()).unwrap();
+        assert_approx_eq!(f32, curve.get_derivative(2.0), 1.0, epsilon = 0.001);
+    }
+
+    #[test]
+    fn curve_queries() {
+        let curve = Curve::new(&[
+            Node::new(0.0, 0.0),
+            Node::with_out(1.0, 1.0, 0.3, 0.0),
+            Node::with_in(2.0, -1.0, -0.3, 0.0),
+            Node::new(3.0, 0.5),
+        ]);
+
+        // every answer agrees with the values get_value interpolates
+        let step = 1.0 / 4096.0;
+        let samples = (0..=4096 * 4)
+            .map(|i| {
+                let time = i as Time * step - 0.5;
+                (time, curve.get_value(time))
+            })
+            .collect::<Vec<_>>();
+
+        let crossings = curve.crossings(0.25, -0.5, 3.5);
+        let sampled = query::crossings(&samples, 0.25);
+        assert_eq!(crossings.len(), sampled.len());
+        for (found, sampled) in crossings.iter().zip(&sampled) {
+            assert_eq!(found.direction, sampled.direction);
+            assert!((found.time - sampled.time).abs() < 2.0 * step);
+        }
+
+        let extrema = curve.extrema(-0.5, 3.5);
+        let kinds = extrema.iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [query::ExtremumKind::Maximum, query::ExtremumKind::Minimum]
+        );
+        assert_approx_eq!(f32, extrema[0].value, 1.0, epsilon = 0.01);
+        assert_approx_eq!(f32, extrema[1].value, -1.0, epsilon = 0.01);
+
+        let (min, max) = curve.value_range(0.5, 2.5);
+        let (expected_min, expected_max) = query::value_range(
+            &samples
+                .iter()
+                .copied()
+                .filter(|&(t, _)| (0.5..=2.5).contains(&t))
+                .collect::<Vec<_>>(),
+        );
+        assert_approx_eq!(f32, min, expected_min, epsilon = 1e-4);
+        assert_approx_eq!(f32, max, expected_max, epsilon = 1e-4);
+
+        // whole segments on one side of the threshold are skipped
+        let vertices = curve.vertices(0.0, 3.0, |cps| query::hull_excludes(cps, 5.0));
+        assert!(vertices.len() < 10);
+    }
 }
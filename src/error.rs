@@ -0,0 +1,249 @@
+use serde_json::{Map, Value};
+use std::{error::Error, fmt, io};
+
+/// Error returned when a project file can not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// Reading the project data failed.
+    Io(io::Error),
+    /// The project data is not valid JSON.
+    Json(serde_json::Error),
+    /// The project data is valid JSON, but does not describe a project.
+    Schema {
+        /// JSON path of the offending value, e.g. `curves[3].nodes`.
+        path: String,
+        /// Description of the problem.
+        message: String,
+    },
+}
+
+impl LoadError {
+    pub(crate) fn schema(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::Schema {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Prefixes the path of a schema error with the path of its parent value.
+    pub(crate) fn within(self, parent: &str) -> Self {
+        match self {
+            Self::Schema { path, message } => Self::Schema {
+                path: join_path(parent, &path),
+                message,
+            },
+            other => other,
+        }
+    }
+}
+
+fn join_path(parent: &str, child: &str) -> String {
+    if parent.is_empty() {
+        child.to_string()
+    } else if child.is_empty() {
+        parent.to_string()
+    } else if child.starts_with('[') {
+        format!("{}{}", parent, child)
+    } else {
+        format!("{}.{}", parent, child)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read project: {}", err),
+            Self::Json(err) => write!(f, "failed to parse project: {}", err),
+            Self::Schema { path, message } if path.is_empty() => {
+                write!(f, "invalid project: {}", message)
+            }
+            Self::Schema { path, message } => {
+                write!(f, "invalid project at `{}`: {}", path, message)
+            }
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Json(err) => Some(err),
+            Self::Schema { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            Self::Io(err.into())
+        } else {
+            Self::Json(err)
+        }
+    }
+}
+
+/// Error returned when a project can not be saved.
+#[derive(Debug)]
+pub enum SaveError {
+    /// There is no project to save.
+    NotLoaded,
+    /// Writing the project data failed.
+    Io(io::Error),
+    /// A number is NaN or infinite, which JSON can not hold.
+    NonFinite {
+        /// JSON path of the number, e.g. `curves[0].nodes[1][1]`.
+        path: String,
+        value: f64,
+    },
+}
+
+impl SaveError {
+    pub(crate) fn non_finite(path: impl Into<String>, value: f64) -> Self {
+        Self::NonFinite {
+            path: path.into(),
+            value,
+        }
+    }
+
+    /// Prefixes the path of the number with the path of its parent value.
+    pub(crate) fn within(self, parent: &str) -> Self {
+        match self {
+            Self::NonFinite { path, value } => Self::NonFinite {
+                path: join_path(parent, &path),
+                value,
+            },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotLoaded => write!(f, "no project loaded"),
+            Self::Io(err) => write!(f, "failed to write project: {}", err),
+            Self::NonFinite { path, value } => {
+                write!(
+                    f,
+                    "can not save {} at `{}`, numbers must be finite",
+                    value, path
+                )
+            }
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::NotLoaded | Self::NonFinite { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+pub(crate) fn as_object<'v>(
+    json: &'v Value,
+    path: &str,
+) -> Result<&'v Map<String, Value>, LoadError> {
+    json.as_object()
+        .ok_or_else(|| LoadError::schema(path, "expected an object"))
+}
+
+pub(crate) fn as_array<'v>(json: &'v Value, path: &str) -> Result<&'v Vec<Value>, LoadError> {
+    json.as_array()
+        .ok_or_else(|| LoadError::schema(path, "expected an array"))
+}
+
+pub(crate) fn as_f64(json: &Value, path: &str) -> Result<f64, LoadError> {
+    json.as_f64()
+        .ok_or_else(|| LoadError::schema(path, "expected a number"))
+}
+
+/// Looks up an optional field, failing only if it is present with the wrong type.
+pub(crate) fn field<'v, T>(
+    json: &'v Value,
+    key: &str,
+    convert: impl FnOnce(&'v Value) -> Option<T>,
+    expected: &str,
+) -> Result<Option<T>, LoadError> {
+    match json.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => convert(value)
+            .map(Some)
+            .ok_or_else(|| LoadError::schema(key, format!("expected {}", expected))),
+    }
+}
+
+pub(crate) fn field_f64(json: &Value, key: &str) -> Result<Option<f64>, LoadError> {
+    field(json, key, Value::as_f64, "a number")
+}
+
+pub(crate) fn field_u64(json: &Value, key: &str) -> Result<Option<u64>, LoadError> {
+    field(json, key, Value::as_u64, "a non-negative integer")
+}
+
+pub(crate) fn field_bool(json: &Value, key: &str) -> Result<Option<bool>, LoadError> {
+    field(json, key, Value::as_bool, "a boolean")
+}
+
+pub(crate) fn field_array<'v>(
+    json: &'v Value,
+    key: &str,
+) -> Result<Option<&'v Vec<Value>>, LoadError> {
+    field(json, key, Value::as_array, "an array")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_path_nesting() {
+        let err = LoadError::schema("nodes", "too short")
+            .within("curves[3]")
+            .within("");
+
+        match err {
+            LoadError::Schema { path, .. } => assert_eq!(path, "curves[3].nodes"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let err = LoadError::schema("[1]", "expected an array").within("nodes");
+        assert_eq!(
+            err.to_string(),
+            "invalid project at `nodes[1]`: expected an array"
+        );
+    }
+
+    #[test]
+    fn json_io_errors_are_io() {
+        struct Broken;
+
+        impl io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken pipe"))
+            }
+        }
+
+        let err = serde_json::from_reader::<_, Value>(Broken).unwrap_err();
+        assert!(matches!(LoadError::from(err), LoadError::Io(_)));
+
+        let err = serde_json::from_str::<Value>("{ nope").unwrap_err();
+        assert!(matches!(LoadError::from(err), LoadError::Json(_)));
+    }
+}
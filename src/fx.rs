@@ -1,42 +1,100 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::fmt::Debug;
 
-use serde_json::Value;
+use serde_json::{Map, Value};
 
-pub trait FxFn: FnMut(FxContext) -> f32 + 'static {}
+use crate::{
+    error::{self, LoadError, SaveError},
+    json, Time,
+};
+
+/// An fx definition. Curves are precalculated wherever a project is loaded,
+/// so definitions must be free to move to other threads.
+pub trait FxFn: FnMut(FxContext) -> f32 + Send + 'static {}
+
+impl<F: FnMut(FxContext) -> f32 + Send + 'static> FxFn for F {}
 
 pub type FxFnBoxFn = fn() -> Box<dyn FxFn>;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FxSection {
     /// Beginning time of the section.
-    pub time: f32,
+    pub time: Time,
     /// Time length of the section.
-    pub length: f32,
+    pub length: Time,
     /// Row of the section.
     pub row: usize,
     /// Fx definition name of the section.
     pub def: String,
     // Params of the section.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub params: FxParams,
+    /// Whether the section is disabled.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bypass: bool,
+}
+
+impl FxSection {
+    pub(crate) fn from_json(json: &Value) -> Result<Self, LoadError> {
+        error::as_object(json, "")?;
+
+        let def = error::field(json, "def", Value::as_str, "a string")?
+            .ok_or_else(|| LoadError::schema("def", "missing fx definition name"))?;
+
+        let params = match json.get("params") {
+            None | Some(Value::Null) => FxParams::new(),
+            Some(params) => FxParams::from_json(params).map_err(|e| e.within("params"))?,
+        };
+
+        Ok(Self {
+            time: error::field_f64(json, "time")?.unwrap_or(0.0) as _,
+            length: error::field_f64(json, "length")?.unwrap_or(0.0) as _,
+            row: error::field_u64(json, "row")?.unwrap_or(0) as _,
+            def: def.to_string(),
+            params,
+            bypass: error::field_bool(json, "bypass")?.unwrap_or(false),
+        })
+    }
+
+    pub(crate) fn to_json(&self) -> Result<Value, SaveError> {
+        let mut section = serde_json::json!({
+            "def": self.def,
+            "params": self.params.to_json(),
+            "time": json::time(self.time, "time")?,
+            "length": json::time(self.length, "length")?,
+            "row": self.row,
+        });
+
+        if self.bypass {
+            section["bypass"] = Value::Bool(true);
+        }
+
+        Ok(section)
+    }
 }
 
 pub struct FxContext<'x> {
     pub index: usize,
     pub i0: usize,
     pub i1: usize,
-    pub time: f32,
-    pub t0: f32,
-    pub t1: f32,
-    pub delta_time: f32,
+    pub time: Time,
+    pub t0: Time,
+    pub t1: Time,
+    pub delta_time: Time,
     pub value: f32,
     pub progress: f32,
-    pub elapsed: f32,
+    pub elapsed: Time,
     pub resolution: usize,
-    pub length: f32,
+    pub length: Time,
+    pub params: &'x FxParams,
     pub array: &'x [f32],
-    //pub shouldNotInterpolate,
-    //pub setShouldNotInterpolate,
-    pub get_value: &'x dyn Fn(f32) -> f32,
+    /// Whether the value at this index holds until the next one, as set by
+    /// an earlier fx.
+    pub should_not_interpolate: bool,
+    /// Makes the returned value hold until the next one instead of being
+    /// interpolated, e.g. for fx that quantize the curve into steps.
+    pub set_should_not_interpolate: &'x dyn Fn(bool),
+    pub get_value: &'x dyn Fn(Time) -> f32,
     pub init: bool,
     //pub state: FxParams,
 }
@@ -56,19 +114,37 @@ impl Debug for FxContext<'_> {
         .field("elapsed", &self.elapsed)
         .field("resolution", &self.resolution)
         .field("length", &self.length)
+        .field("params", &self.params)
         .field("array", &self.array)
+        .field("should_not_interpolate", &self.should_not_interpolate)
         .field("init", &self.init)
         //.field("state", &self.state)
         .finish_non_exhaustive()
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct FxParams(HashMap<String, Value>);
+/// Parameters of an fx section, in the order of the project file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FxParams(Map<String, Value>);
 
 impl FxParams {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self(Map::new())
+    }
+
+    pub(crate) fn from_json(json: &Value) -> Result<Self, LoadError> {
+        let params = error::as_object(json, "")?
+            .clone();
+
+        Ok(Self(params))
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        Value::Object(self.0.clone())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
     }
 
     pub fn get_f64(&self, name: &str) -> Option<f64> {
@@ -87,3 +163,139 @@ impl FxParams {
         self.0.get(name).and_then(Value::as_bool)
     }
 }
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FxParams {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            self.0
+                .iter()
+                .map(|(name, value)| (name, json::ValueRef(value))),
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FxParams {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ParamsVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ParamsVisitor {
+            type Value = FxParams;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a map of fx params")
+            }
+
+            // entry by entry, so the params keep their order
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut access: A,
+            ) -> Result<FxParams, A::Error> {
+                let mut params = Map::new();
+                while let Some((name, value)) = access.next_entry::<String, json::OwnedValue>()? {
+                    params.insert(name, value.0);
+                }
+
+                Ok(FxParams(params))
+            }
+        }
+
+        deserializer.deserialize_map(ParamsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn section_parse_full() {
+        let json = r#"{
+            "def": "sine",
+            "params": { "amp": 0.5, "freq": 4 },
+            "time": 1.5,
+            "length": 2.0,
+            "row": 1,
+            "bypass": true
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let section = FxSection::from_json(&value).unwrap();
+
+        assert_eq!(section.def, "sine");
+        assert_eq!(section.time, 1.5);
+        assert_eq!(section.length, 2.0);
+        assert_eq!(section.row, 1);
+        assert!(section.bypass);
+        assert_eq!(section.params.get_f64("amp"), Some(0.5));
+        assert_eq!(section.params.get_u64("freq"), Some(4));
+    }
+
+    #[test]
+    fn section_round_trip() {
+        let json = r#"{
+            "def": "sine",
+            "params": { "amp": 0.5, "freq": 4 },
+            "time": 1.5,
+            "length": 2,
+            "row": 1,
+            "bypass": true
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let section = FxSection::from_json(&value).unwrap();
+        assert_eq!(section.to_json().unwrap(), value);
+    }
+
+    #[test]
+    fn params_keep_file_order() {
+        let json = r#"{ "zeta": 1, "alpha": 2, "mid": 3, "beta": 4, "gamma": 5 }"#;
+        let value = serde_json::from_str(json).unwrap();
+        let params = FxParams::from_json(&value).unwrap();
+
+        let saved = params.to_json().to_string();
+        assert_eq!(saved, r#"{"zeta":1,"alpha":2,"mid":3,"beta":4,"gamma":5}"#);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn section_serde() {
+        let json = r#"{
+            "def": "sine",
+            "params": { "mode": "saw", "amp": 0.5, "steps": [1, 2, 3] },
+            "time": 1.5,
+            "length": 2,
+            "row": 1
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let section = FxSection::from_json(&value).unwrap();
+
+        // equality ignores the order of the params, the saved file does not
+        let check = |restored: FxSection| {
+            assert_eq!(restored, section);
+            assert_eq!(
+                restored.params.to_json().to_string(),
+                r#"{"mode":"saw","amp":0.5,"steps":[1,2,3]}"#
+            );
+        };
+
+        let bytes = rmp_serde::to_vec(&section).unwrap();
+        check(rmp_serde::from_slice(&bytes).unwrap());
+
+        let text = ron::to_string(&section).unwrap();
+        check(ron::from_str(&text).unwrap());
+    }
+
+    #[test]
+    fn section_parse_invalid() {
+        let value = serde_json::from_str(r#"{ "time": 1 }"#).unwrap();
+        let err = FxSection::from_json(&value).unwrap_err();
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "def"));
+
+        let value = serde_json::from_str(r#"{ "def": "sine", "params": [] }"#).unwrap();
+        let err = FxSection::from_json(&value).unwrap_err();
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "params"));
+    }
+}
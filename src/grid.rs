@@ -0,0 +1,198 @@
+use crate::Time;
+
+/// Most values a curve precalculates, e.g. 46 hours of curve at the default
+/// resolution of 100.
+pub(crate) const MAX_LEN: usize = 1 << 24;
+
+/// Times of the precalculated values of a curve.
+///
+/// Value `i` belongs to `i / resolution`, except for the last one, which
+/// belongs to the end of the curve. Every time is computed the same way
+/// everywhere, so looking up a value at the time it belongs to gives exactly
+/// that value.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Grid {
+    resolution: usize,
+    length: Time,
+    len: usize,
+}
+
+impl Grid {
+    /// Whether the values of a curve of the given length stay within
+    /// [`MAX_LEN`].
+    pub(crate) fn fits(resolution: usize, length: Time) -> bool {
+        let steps = length * resolution as Time;
+        steps.is_nan() || steps.ceil() < MAX_LEN as Time
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the curve does not [fit](Grid::fits).
+    pub(crate) fn new(resolution: usize, length: Time) -> Self {
+        assert!(
+            Self::fits(resolution, length),
+            "a curve of length {} needs more than {} values at resolution {}",
+            length,
+            MAX_LEN,
+            resolution
+        );
+
+        let mut grid = Self {
+            resolution,
+            length,
+            len: usize::MAX,
+        };
+
+        // one value per step before the end, and one at the end
+        grid.len = grid.steps_before(length) + 1;
+        grid
+    }
+
+    pub(crate) fn resolution(&self) -> usize {
+        self.resolution
+    }
+
+    /// Number of precalculated values.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn time(&self, index: usize) -> Time {
+        if index + 1 >= self.len {
+            self.length
+        } else {
+            self.step_time(index)
+        }
+    }
+
+    fn step_time(&self, index: usize) -> Time {
+        index as Time / self.resolution as Time
+    }
+
+    /// Number of steps that lie before the given time.
+    fn steps_before(&self, time: Time) -> usize {
+        if time.is_nan() || time <= 0.0 {
+            return 0;
+        }
+
+        let mut steps = (time * self.resolution as Time).ceil() as usize;
+        while steps > 0 && self.step_time(steps - 1) >= time {
+            steps -= 1;
+        }
+        while self.step_time(steps) < time {
+            steps += 1;
+        }
+
+        steps
+    }
+
+    /// Index of the last value at or before the given time, or 0 before the
+    /// first one.
+    pub(crate) fn floor_index(&self, time: Time) -> usize {
+        if time >= self.length {
+            return self.len - 1;
+        }
+
+        if time.is_nan() || time <= 0.0 {
+            return 0;
+        }
+
+        let index = (time * self.resolution as Time).floor() as usize;
+        if self.step_time(index) > time {
+            index - 1
+        } else if self.step_time(index + 1) <= time {
+            index + 1
+        } else {
+            index
+        }
+    }
+
+    /// Index of the first value at or after the given time, or the number
+    /// of values past the last one.
+    pub(crate) fn ceil_index(&self, time: Time) -> usize {
+        let index = self.floor_index(time);
+        if self.time(index) < time {
+            index + 1
+        } else {
+            index
+        }
+    }
+
+    /// Index of the value at or before the given time, and how far the time
+    /// is on the way to the next value. The time must lie within the curve.
+    pub(crate) fn position(&self, time: Time) -> (usize, f32) {
+        let index = self.floor_index(time);
+        let (t0, t1) = (self.time(index), self.time(index + 1));
+        let fract: f32 = ((time - t0) / (t1 - t0)) as _;
+        (index, fract)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_fits() {
+        assert!(Grid::fits(100, 165_000.0));
+        assert!(!Grid::fits(100, 170_000.0));
+        assert!(!Grid::fits(usize::MAX, 1.0));
+        assert!(Grid::fits(100, -1.0));
+    }
+
+    #[test]
+    fn grid_times() {
+        let grid = Grid::new(10, 0.25);
+        assert_eq!(grid.len(), 4);
+        let times = (0..grid.len()).map(|i| grid.time(i)).collect::<Vec<_>>();
+        assert_eq!(times, [0.0, 0.1, 0.2, 0.25]);
+
+        // ends on a step
+        let grid = Grid::new(10, 0.3);
+        assert_eq!(grid.len(), 4);
+        assert_eq!(grid.time(3), 0.3);
+
+        let grid = Grid::new(100, 0.0);
+        assert_eq!(grid.len(), 1);
+        assert_eq!(grid.time(0), 0.0);
+    }
+
+    #[test]
+    fn grid_indices() {
+        let grid = Grid::new(10, 0.25);
+
+        assert_eq!(grid.floor_index(-1.0), 0);
+        assert_eq!(grid.floor_index(0.1), 1);
+        assert_eq!(grid.floor_index(0.15), 1);
+        assert_eq!(grid.floor_index(0.25), 3);
+        assert_eq!(grid.floor_index(7.0), 3);
+        assert_eq!(grid.floor_index(Time::NAN), 0);
+
+        assert_eq!(grid.ceil_index(-1.0), 0);
+        assert_eq!(grid.ceil_index(0.1), 1);
+        assert_eq!(grid.ceil_index(0.15), 2);
+        assert_eq!(grid.ceil_index(0.22), 3);
+        assert_eq!(grid.ceil_index(7.0), 4);
+
+        assert_eq!(grid.position(0.2), (2, 0.0));
+        let (index, fract) = grid.position(0.225);
+        assert_eq!(index, 2);
+        assert!((fract - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn grid_times_round_trip() {
+        // times that do not survive `i / resolution * resolution` exactly
+        for resolution in 1..=240 {
+            let grid = Grid::new(resolution, 3.0);
+            for i in 0..grid.len() {
+                assert_eq!(grid.floor_index(grid.time(i)), i, "{} at {}", i, resolution);
+                assert_eq!(grid.ceil_index(grid.time(i)), i, "{} at {}", i, resolution);
+            }
+
+            for i in 0..grid.len() - 1 {
+                assert_eq!(grid.position(grid.time(i)), (i, 0.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Scalar channels that make up one vector or color.
+//!
+//! The editor only knows scalar channels, so vectors are animated as one
+//! channel per component, named by a shared prefix and a component suffix,
+//! e.g. `camera.pos.x`, `camera.pos.y` and `camera.pos.z`.
+
+use crate::{
+    channel::{Channel, ChannelId},
+    state::SaveState,
+    validate::Diagnostic,
+    Time,
+};
+
+pub const XY: [&str; 2] = ["x", "y"];
+pub const XYZ: [&str; 3] = ["x", "y", "z"];
+pub const XYZW: [&str; 4] = ["x", "y", "z", "w"];
+pub const RGB: [&str; 3] = ["r", "g", "b"];
+pub const RGBA: [&str; 4] = ["r", "g", "b", "a"];
+pub const HSV: [&str; 3] = ["h", "s", "v"];
+
+/// Channels evaluated together as the components of a vector.
+///
+/// Components without a channel evaluate to 0, see
+/// [`ChannelGroup::resolve`]. Groups are looked up for one state; channel
+/// handles may change when another version of the project is loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelGroup<const N: usize> {
+    prefix: String,
+    names: [String; N],
+    ids: [Option<ChannelId>; N],
+}
+
+impl<const N: usize> ChannelGroup<N> {
+    /// Looks up the channels `<prefix>.<component>` for every component,
+    /// along with a warning for each one that is missing.
+    pub fn resolve(
+        state: &SaveState,
+        prefix: &str,
+        components: [&str; N],
+    ) -> (Self, Vec<Diagnostic>) {
+        let group = Self::new(state, prefix, components);
+        let diagnostics = group.diagnostics();
+        (group, diagnostics)
+    }
+
+    /// Looks up the channels `<prefix>.<component>` for every component.
+    pub fn new(state: &SaveState, prefix: &str, components: [&str; N]) -> Self {
+        let names = components.map(|component| format!("{}.{}", prefix, component));
+        let ids = names.each_ref().map(|name| state.channel_id(name));
+
+        Self {
+            prefix: prefix.to_string(),
+            names,
+            ids,
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn ids(&self) -> [Option<ChannelId>; N] {
+        self.ids
+    }
+
+    /// Whether every component has a channel.
+    pub fn is_complete(&self) -> bool {
+        self.ids.iter().all(Option::is_some)
+    }
+
+    /// Names of the channels that are missing.
+    pub fn missing(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .zip(&self.ids)
+            .filter(|(_, id)| id.is_none())
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// One warning per missing channel. They point at the channel list, since
+    /// the missing channels are nowhere in the file.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.missing()
+            .map(|name| {
+                Diagnostic::warning(
+                    "channels",
+                    format!("`{}` has no channel `{}`, it stays 0", self.prefix, name),
+                )
+            })
+            .collect()
+    }
+
+    /// Evaluates every component at the given time, see
+    /// [`Channel::get_value`].
+    pub fn get_value(&self, state: &SaveState, time: Time) -> [f32; N] {
+        self.ids.map(|id| {
+            id.and_then(|id| state.channel_by_id(id))
+                .map_or(0.0, |channel| channel.get_value(time))
+        })
+    }
+
+    /// Collects a value of every component's channel, e.g. its
+    /// [`Channel::current_value`] during playback.
+    pub(crate) fn map<'a>(
+        &self,
+        channel: impl Fn(ChannelId) -> Option<&'a Channel>,
+        value: impl Fn(&Channel) -> f32,
+    ) -> [f32; N] {
+        self.ids.map(|id| id.and_then(&channel).map_or(0.0, &value))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Rgb,
+    /// Hue in turns, so both 0 and 1 are red, and saturation and value
+    /// from 0 to 1.
+    Hsv,
+}
+
+/// Three channels that animate a color.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorGroup {
+    pub channels: ChannelGroup<3>,
+    pub space: ColorSpace,
+}
+
+impl ColorGroup {
+    /// Looks up the color like [`ColorGroup::new`], along with a warning for
+    /// each channel that is missing.
+    pub fn resolve(state: &SaveState, prefix: &str) -> (Self, Vec<Diagnostic>) {
+        let group = Self::new(state, prefix);
+        let diagnostics = group.channels.diagnostics();
+        (group, diagnostics)
+    }
+
+    /// Looks up `<prefix>.h`, `.s` and `.v` if the project has any of them,
+    /// and `<prefix>.r`, `.g` and `.b` otherwise.
+    pub fn new(state: &SaveState, prefix: &str) -> Self {
+        let hsv = ChannelGroup::new(state, prefix, HSV);
+        if hsv.ids.iter().any(Option::is_some) {
+            return Self {
+                channels: hsv,
+                space: ColorSpace::Hsv,
+            };
+        }
+
+        Self {
+            channels: ChannelGroup::new(state, prefix, RGB),
+            space: ColorSpace::Rgb,
+        }
+    }
+
+    /// Evaluates the color at the given time, as RGB.
+    pub fn get_rgb(&self, state: &SaveState, time: Time) -> [f32; 3] {
+        self.to_rgb(self.channels.get_value(state, time))
+    }
+
+    /// Converts values of the group's channels to RGB.
+    pub fn to_rgb(&self, values: [f32; 3]) -> [f32; 3] {
+        match self.space {
+            ColorSpace::Rgb => values,
+            ColorSpace::Hsv => hsv_to_rgb(values),
+        }
+    }
+}
+
+pub fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let h = h.rem_euclid(1.0) * 6.0;
+    let f = |n: f32| {
+        let k = (n + h) % 6.0;
+        v - v * s * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+
+    [f(5.0), f(3.0), f(1.0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{validate::Severity, Automaton};
+    use float_cmp::assert_approx_eq;
+
+    const PROJECT: &str = r#"{
+        "channels": [
+            ["camera.pos.x", { "items": [{ "time": 0, "length": 1, "value": 1 }] }],
+            ["camera.pos.z", { "items": [{ "time": 0, "length": 1, "value": 3 }] }],
+            ["camera.pos.y", { "items": [{ "time": 0, "length": 1, "value": 2 }] }],
+            ["fog.h", { "items": [{ "time": 0, "length": 1, "value": 0.5 }] }],
+            ["fog.s", { "items": [{ "time": 0, "length": 1, "value": 1 }] }],
+            ["fog.v", { "items": [{ "time": 0, "length": 1, "value": 0.5 }] }],
+            ["speed", { "items": [] }]
+        ]
+    }"#;
+
+    #[test]
+    fn channel_groups() {
+        let mut automaton = Automaton::new();
+        automaton.load(PROJECT.as_bytes()).unwrap();
+        let state = automaton.state().unwrap().clone();
+
+        assert_eq!(state.group_prefixes(), ["camera.pos", "fog"]);
+
+        let position = ChannelGroup::new(&state, "camera.pos", XYZ);
+        assert!(position.is_complete());
+        assert_eq!(position.get_value(&state, 0.5), [1.0, 2.0, 3.0]);
+        assert_eq!(automaton.auto_group(&position), [0.0; 3]);
+
+        automaton.update(0.5);
+        assert_eq!(automaton.auto_group(&position), [1.0, 2.0, 3.0]);
+
+        let rotation = ChannelGroup::new(&state, "camera.pos", XYZW);
+        assert!(!rotation.is_complete());
+        assert_eq!(rotation.missing().collect::<Vec<_>>(), ["camera.pos.w"]);
+        assert_eq!(rotation.get_value(&state, 0.5), [1.0, 2.0, 3.0, 0.0]);
+
+        let diagnostics = rotation.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].location, "channels");
+    }
+
+    #[test]
+    fn resolve_groups() {
+        let mut automaton = Automaton::new();
+        automaton.load(PROJECT.as_bytes()).unwrap();
+        let state = automaton.state().unwrap();
+
+        let (position, diagnostics) = ChannelGroup::resolve(state, "camera.pos", XYZ);
+        assert!(position.is_complete());
+        assert!(diagnostics.is_empty());
+
+        let (rotation, diagnostics) = ChannelGroup::resolve(state, "camera.pos", XYZW);
+        assert_eq!(rotation, ChannelGroup::new(state, "camera.pos", XYZW));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].location, "channels");
+        assert_eq!(
+            diagnostics[0].message,
+            "`camera.pos` has no channel `camera.pos.w`, it stays 0"
+        );
+
+        let (fog, diagnostics) = ColorGroup::resolve(state, "fog");
+        assert_eq!(fog.space, ColorSpace::Hsv);
+        assert!(diagnostics.is_empty());
+
+        let (_, diagnostics) = ColorGroup::resolve(state, "sky");
+        let messages = diagnostics.iter().map(|d| d.message.as_str());
+        assert_eq!(
+            messages.collect::<Vec<_>>(),
+            [
+                "`sky` has no channel `sky.r`, it stays 0",
+                "`sky` has no channel `sky.g`, it stays 0",
+                "`sky` has no channel `sky.b`, it stays 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn color_groups() {
+        let mut automaton = Automaton::new();
+        automaton.load(PROJECT.as_bytes()).unwrap();
+        let state = automaton.state().unwrap();
+
+        let fog = ColorGroup::new(state, "fog");
+        assert_eq!(fog.space, ColorSpace::Hsv);
+        assert_eq!(fog.get_rgb(state, 0.5), [0.0, 0.5, 0.5]);
+
+        let missing = ColorGroup::new(state, "sky");
+        assert_eq!(missing.space, ColorSpace::Rgb);
+        assert_eq!(missing.channels.diagnostics().len(), 3);
+    }
+
+    #[test]
+    fn hsv_conversion() {
+        let cases = [
+            ([0.0, 1.0, 1.0], [1.0, 0.0, 0.0]),
+            ([1.0 / 3.0, 1.0, 1.0], [0.0, 1.0, 0.0]),
+            ([2.0 / 3.0, 1.0, 1.0], [0.0, 0.0, 1.0]),
+            ([1.0, 1.0, 1.0], [1.0, 0.0, 0.0]),
+            ([-0.5, 0.5, 0.8], [0.4, 0.8, 0.8]),
+            ([0.25, 0.0, 0.3], [0.3, 0.3, 0.3]),
+        ];
+
+        for (hsv, expected) in cases {
+            let rgb = hsv_to_rgb(hsv);
+            for (c, e) in rgb.iter().zip(&expected) {
+                assert_approx_eq!(f32, *c, *e, epsilon = 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use serde_json::{Map, Value};
+
+use crate::{
+    error::{self, LoadError, SaveError},
+    json, Time,
+};
+
+/// Editor settings saved along with the project.
+///
+/// Settings this crate does not know about are kept as they are, so they
+/// survive a save.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuiSettings {
+    pub snap_time_active: bool,
+    pub snap_time_interval: Time,
+    pub snap_value_active: bool,
+    pub snap_value_interval: f32,
+    pub snap_beat_active: bool,
+    /// Tempo of the beat grid, in beats per minute.
+    pub bpm: f32,
+    /// Time of the first beat, in seconds.
+    pub beat_offset: Time,
+    pub use_beat_in_gui: bool,
+    pub minimized_precision_time: u32,
+    pub minimized_precision_value: u32,
+    extra: Map<String, Value>,
+}
+
+impl Default for GuiSettings {
+    fn default() -> Self {
+        Self {
+            snap_time_active: false,
+            snap_time_interval: 0.1,
+            snap_value_active: false,
+            snap_value_interval: 0.1,
+            snap_beat_active: false,
+            bpm: 140.0,
+            beat_offset: 0.0,
+            use_beat_in_gui: false,
+            minimized_precision_time: 3,
+            minimized_precision_value: 3,
+            extra: Map::new(),
+        }
+    }
+}
+
+const KNOWN_KEYS: &[&str] = &[
+    "snapTimeActive",
+    "snapTimeInterval",
+    "snapValueActive",
+    "snapValueInterval",
+    "snapBeatActive",
+    "bpm",
+    "beatOffset",
+    "useBeatInGUI",
+    "minimizedPrecisionTime",
+    "minimizedPrecisionValue",
+];
+
+impl GuiSettings {
+    pub(crate) fn from_json(json: &Value) -> Result<Self, LoadError> {
+        let object = error::as_object(json, "")?;
+        let default = Self::default();
+
+        let precision = |key: &str, default: u32| -> Result<u32, LoadError> {
+            Ok(error::field_u64(json, key)?.map_or(default, |v| v as _))
+        };
+
+        Ok(Self {
+            snap_time_active: error::field_bool(json, "snapTimeActive")?
+                .unwrap_or(default.snap_time_active),
+            snap_time_interval: error::field_f64(json, "snapTimeInterval")?
+                .map_or(default.snap_time_interval, |v| v as _),
+            snap_value_active: error::field_bool(json, "snapValueActive")?
+                .unwrap_or(default.snap_value_active),
+            snap_value_interval: error::field_f64(json, "snapValueInterval")?
+                .map_or(default.snap_value_interval, |v| v as _),
+            snap_beat_active: error::field_bool(json, "snapBeatActive")?
+                .unwrap_or(default.snap_beat_active),
+            bpm: error::field_f64(json, "bpm")?.map_or(default.bpm, |v| v as _),
+            beat_offset: error::field_f64(json, "beatOffset")?
+                .map_or(default.beat_offset, |v| v as _),
+            use_beat_in_gui: error::field_bool(json, "useBeatInGUI")?
+                .unwrap_or(default.use_beat_in_gui),
+            minimized_precision_time: precision(
+                "minimizedPrecisionTime",
+                default.minimized_precision_time,
+            )?,
+            minimized_precision_value: precision(
+                "minimizedPrecisionValue",
+                default.minimized_precision_value,
+            )?,
+            extra: object
+                .iter()
+                .filter(|(key, _)| !KNOWN_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        })
+    }
+
+    pub(crate) fn to_json(&self) -> Result<Value, SaveError> {
+        let mut settings = self.extra.clone();
+        let mut insert = |key: &str, value: Value| {
+            settings.insert(key.to_string(), value);
+        };
+
+        insert("snapTimeActive", Value::Bool(self.snap_time_active));
+        insert(
+            "snapTimeInterval",
+            json::time(self.snap_time_interval, "snapTimeInterval")?,
+        );
+        insert("snapValueActive", Value::Bool(self.snap_value_active));
+        insert(
+            "snapValueInterval",
+            json::number(self.snap_value_interval, "snapValueInterval")?,
+        );
+        insert("snapBeatActive", Value::Bool(self.snap_beat_active));
+        insert("bpm", json::number(self.bpm, "bpm")?);
+        insert("beatOffset", json::time(self.beat_offset, "beatOffset")?);
+        insert("useBeatInGUI", Value::Bool(self.use_beat_in_gui));
+        insert(
+            "minimizedPrecisionTime",
+            Value::from(self.minimized_precision_time),
+        );
+        insert(
+            "minimizedPrecisionValue",
+            Value::from(self.minimized_precision_value),
+        );
+
+        Ok(Value::Object(settings))
+    }
+
+    /// Returns the beat grid the editor draws with these settings.
+    pub fn beat_grid(&self) -> BeatGrid {
+        BeatGrid::new(self.bpm, self.beat_offset)
+    }
+}
+
+/// Converts between seconds and musical time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeatGrid {
+    /// Tempo, in beats per minute.
+    pub bpm: f32,
+    /// Time of beat 0, in seconds.
+    pub offset: Time,
+    pub beats_per_bar: u32,
+}
+
+impl BeatGrid {
+    pub fn new(bpm: f32, offset: Time) -> Self {
+        Self {
+            bpm,
+            offset,
+            beats_per_bar: 4,
+        }
+    }
+
+    pub fn with_beats_per_bar(self, beats_per_bar: u32) -> Self {
+        Self {
+            beats_per_bar,
+            ..self
+        }
+    }
+
+    /// Length of one beat, in seconds.
+    pub fn beat_length(&self) -> Time {
+        60.0 / Time::from(self.bpm)
+    }
+
+    /// Length of one bar, in seconds.
+    pub fn bar_length(&self) -> Time {
+        self.beat_length() * self.beats_per_bar as Time
+    }
+
+    pub fn time_to_beat(&self, time: Time) -> Time {
+        (time - self.offset) / self.beat_length()
+    }
+
+    pub fn beat_to_time(&self, beat: Time) -> Time {
+        self.offset + beat * self.beat_length()
+    }
+
+    pub fn time_to_bar(&self, time: Time) -> Time {
+        self.time_to_beat(time) / self.beats_per_bar as Time
+    }
+
+    pub fn bar_to_time(&self, bar: Time) -> Time {
+        self.beat_to_time(bar * self.beats_per_bar as Time)
+    }
+
+    /// Snaps a time to the closest multiple of `beats` beats, e.g. `0.25` for
+    /// sixteenth notes.
+    pub fn quantize(&self, time: Time, beats: Time) -> Time {
+        let beat = self.time_to_beat(time);
+        self.beat_to_time((beat / beats).round() * beats)
+    }
+
+    /// Like [`BeatGrid::quantize`], but always snaps to the grid line at or
+    /// before the given time.
+    pub fn quantize_floor(&self, time: Time, beats: Time) -> Time {
+        let beat = self.time_to_beat(time);
+        self.beat_to_time((beat / beats).floor() * beats)
+    }
+}
+
+impl Default for BeatGrid {
+    fn default() -> Self {
+        GuiSettings::default().beat_grid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    #[test]
+    fn settings_parse() {
+        let json = r#"{
+            "snapBeatActive": true,
+            "bpm": 120,
+            "beatOffset": 0.25,
+            "minimizedPrecisionTime": 2,
+            "someNewSetting": [1, 2]
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let settings = GuiSettings::from_json(&value).unwrap();
+
+        assert!(settings.snap_beat_active);
+        assert_eq!(settings.bpm, 120.0);
+        assert_eq!(settings.beat_offset, 0.25);
+        assert_eq!(settings.minimized_precision_time, 2);
+        assert_eq!(settings.snap_time_interval, 0.1);
+
+        let written = settings.to_json().unwrap();
+        assert_eq!(written["someNewSetting"], value["someNewSetting"]);
+        assert_eq!(written["snapTimeInterval"], 0.1);
+        assert_eq!(GuiSettings::from_json(&written).unwrap(), settings);
+    }
+
+    #[test]
+    fn settings_parse_invalid() {
+        let value = serde_json::from_str(r#"{ "bpm": "fast" }"#).unwrap();
+        let err = GuiSettings::from_json(&value).unwrap_err();
+
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "bpm"));
+    }
+
+    #[test]
+    fn beat_grid_conversions() {
+        let grid = BeatGrid::new(120.0, 1.0);
+
+        assert_approx_eq!(Time, grid.beat_length(), 0.5);
+        assert_approx_eq!(Time, grid.bar_length(), 2.0);
+        assert_approx_eq!(Time, grid.time_to_beat(1.0), 0.0);
+        assert_approx_eq!(Time, grid.time_to_beat(3.0), 4.0);
+        assert_approx_eq!(Time, grid.beat_to_time(4.0), 3.0);
+        assert_approx_eq!(Time, grid.time_to_bar(5.0), 2.0);
+        assert_approx_eq!(Time, grid.bar_to_time(2.0), 5.0);
+
+        let grid = grid.with_beats_per_bar(3);
+        assert_approx_eq!(Time, grid.bar_to_time(2.0), 4.0);
+    }
+
+    #[test]
+    fn beat_grid_quantize() {
+        let grid = BeatGrid::new(120.0, 0.1);
+
+        assert_approx_eq!(Time, grid.quantize(0.3, 1.0), 0.1);
+        assert_approx_eq!(Time, grid.quantize(0.4, 1.0), 0.6);
+        assert_approx_eq!(Time, grid.quantize(0.4, 0.25), 0.35);
+        assert_approx_eq!(Time, grid.quantize_floor(0.55, 1.0), 0.1);
+        assert_approx_eq!(Time, grid.quantize_floor(0.05, 1.0), -0.4);
+    }
+}
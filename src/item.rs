@@ -1,54 +1,157 @@
 use serde_json::Value;
 use std::sync::Arc;
 
-use crate::curve::Curve;
+use crate::{
+    curve::Curve,
+    error::{self, LoadError, SaveError},
+    json, Time,
+};
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Item {
-    pub time: f32,
-    pub length: f32,
+    pub time: Time,
+    pub length: Time,
     pub value: f32,
-    pub offset: f32,
+    pub offset: Time,
     pub speed: f32,
     pub amp: f32,
     pub reset: bool,
     pub curve: Option<Arc<Curve>>,
+    /// Index of a curve the project does not have. The item plays without a
+    /// curve, but saving writes the index back.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unresolved_curve: Option<usize>,
 }
 
 impl Item {
-    pub(crate) fn from_json(json: &Value, curves: &[Arc<Curve>]) -> Self {
-        Self {
-            time: json.get("time").and_then(Value::as_f64).unwrap_or(0.0) as _,
-            length: json.get("length").and_then(Value::as_f64).unwrap_or(0.0) as _,
-            value: json.get("value").and_then(Value::as_f64).unwrap_or(0.0) as _,
-            offset: json.get("offset").and_then(Value::as_f64).unwrap_or(0.0) as _,
-            speed: json.get("speed").and_then(Value::as_f64).unwrap_or(1.0) as _,
-            amp: json.get("amp").and_then(Value::as_f64).unwrap_or(1.0) as _,
-            reset: json.get("reset").and_then(Value::as_bool).unwrap_or(false) as _,
-            curve: json
-                .get("curve")
-                .and_then(Value::as_u64)
-                .and_then(|index| curves.get(index as usize))
-                .cloned(),
+    pub(crate) fn from_json(json: &Value, curves: &[Arc<Curve>]) -> Result<Self, LoadError> {
+        error::as_object(json, "")?;
+
+        let index = error::field_u64(json, "curve")?.map(|index| index as usize);
+        let curve = index.and_then(|index| curves.get(index)).cloned();
+
+        Ok(Self {
+            time: error::field_f64(json, "time")?.unwrap_or(0.0) as _,
+            length: error::field_f64(json, "length")?.unwrap_or(0.0) as _,
+            value: error::field_f64(json, "value")?.unwrap_or(0.0) as _,
+            offset: error::field_f64(json, "offset")?.unwrap_or(0.0) as _,
+            speed: error::field_f64(json, "speed")?.unwrap_or(1.0) as _,
+            amp: error::field_f64(json, "amp")?.unwrap_or(1.0) as _,
+            reset: error::field_bool(json, "reset")?.unwrap_or(false),
+            unresolved_curve: index.filter(|_| curve.is_none()),
+            curve,
+        })
+    }
+
+    /// Writes the item, leaving out fields that hold their default value.
+    pub(crate) fn to_json(&self, curves: &[Arc<Curve>]) -> Result<Value, SaveError> {
+        let mut item = serde_json::json!({
+            "time": json::time(self.time, "time")?,
+            "length": json::time(self.length, "length")?,
+        });
+
+        if self.value != 0.0 {
+            item["value"] = json::number(self.value, "value")?;
+        }
+
+        if self.reset {
+            item["reset"] = Value::Bool(true);
+        }
+
+        if let Some(index) = self.curve_index(curves) {
+            item["curve"] = Value::from(index);
+
+            if self.offset != 0.0 {
+                item["offset"] = json::time(self.offset, "offset")?;
+            }
+
+            if self.speed != 1.0 {
+                item["speed"] = json::number(self.speed, "speed")?;
+            }
+
+            if self.amp != 1.0 {
+                item["amp"] = json::number(self.amp, "amp")?;
+            }
+        }
+
+        Ok(item)
+    }
+
+    /// Index of the item's curve in the given list, or the index it could
+    /// not be resolved from.
+    pub(crate) fn curve_index(&self, curves: &[Arc<Curve>]) -> Option<usize> {
+        match &self.curve {
+            Some(curve) => curves.iter().position(|c| Arc::ptr_eq(c, curve)),
+            None => self.unresolved_curve,
         }
     }
 
-    pub fn end(&self) -> f32 {
+    pub fn end(&self) -> Time {
         self.time + self.length
     }
 
-    pub fn get_value(&self, time: f32) -> f32 {
+    /// Maps time since the beginning of the item to time on its curve.
+    fn curve_time(&self, elapsed: Time) -> Time {
+        self.offset + elapsed * Time::from(self.speed)
+    }
+
+    pub fn get_value(&self, time: Time) -> f32 {
         if self.reset && self.length <= time {
             return 0.0;
         }
 
         if let Some(curve) = &self.curve {
-            let t = self.offset + time * self.speed;
+            let t = self.curve_time(time);
             return self.value + self.amp * curve.get_value(t);
         }
 
         self.value
     }
+
+    /// Corners of the polyline [`Item::get_value`] follows between the given
+    /// elapsed times, see [`Curve::vertices`].
+    pub(crate) fn vertices(
+        &self,
+        e0: Time,
+        e1: Time,
+        skip: impl Fn([f32; 4]) -> bool,
+    ) -> Vec<(Time, f32)> {
+        let curve = match &self.curve {
+            Some(curve) if self.speed != 0.0 => curve,
+            _ => return vec![(e0, self.get_value(e0)), (e1, self.get_value(e0))],
+        };
+
+        let (c0, c1) = (self.curve_time(e0), self.curve_time(e1));
+        let skip = |cps: [f32; 4]| skip(cps.map(|c| self.value + self.amp * c));
+        let mut vertices = curve.vertices(c0.min(c1), c0.max(c1), skip);
+        if self.speed < 0.0 {
+            vertices.reverse();
+        }
+
+        for (time, value) in &mut vertices {
+            *time = (*time - self.offset) / Time::from(self.speed);
+            *value = self.value + self.amp * *value;
+        }
+
+        vertices
+    }
+
+    /// First and second derivative of [`Item::get_value`], scaled by the
+    /// item's `speed` and `amp`.
+    pub fn get_derivatives(&self, time: Time) -> (f32, f32) {
+        match &self.curve {
+            Some(curve) if time < self.length || !self.reset => {
+                let t = self.curve_time(time);
+                let scale = self.amp * self.speed;
+                (
+                    scale * curve.get_derivative(t),
+                    scale * self.speed * curve.get_second_derivative(t),
+                )
+            }
+            _ => (0.0, 0.0),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -66,7 +169,7 @@ mod test {
         }"#;
 
         let value = serde_json::from_str(json).unwrap();
-        let actual = Item::from_json(&value, &[]);
+        let actual = Item::from_json(&value, &[]).unwrap();
 
         let expected = Item {
             time: 7.5,
@@ -77,6 +180,7 @@ mod test {
             amp: 1.0,
             reset: true,
             curve: None,
+            unresolved_curve: None,
         };
 
         assert_eq!(actual, expected);
@@ -94,7 +198,7 @@ mod test {
         let curve = Arc::new(Curve::new(nodes));
 
         let value = serde_json::from_str(json).unwrap();
-        let actual = Item::from_json(&value, &[curve.clone()]);
+        let actual = Item::from_json(&value, std::slice::from_ref(&curve)).unwrap();
 
         let expected = Item {
             time: 3.1,
@@ -105,8 +209,65 @@ mod test {
             amp: 1.0,
             reset: false,
             curve: Some(curve),
+            unresolved_curve: None,
         };
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn item_round_trip() {
+        let json = r#"{
+            "time": 3.1,
+            "length": 2,
+            "value": -1,
+            "reset": true,
+            "curve": 0,
+            "offset": 0.5,
+            "amp": 0.25
+        }"#;
+
+        let nodes = &[Node::new(1.0, 2.0), Node::new(3.0, 4.0)];
+        let curve = Arc::new(Curve::new(nodes));
+
+        let value = serde_json::from_str(json).unwrap();
+        let item = Item::from_json(&value, std::slice::from_ref(&curve)).unwrap();
+
+        assert_eq!(item.to_json(&[curve]).unwrap(), value);
+    }
+
+    #[cfg(feature = "f64-time")]
+    #[test]
+    fn item_round_trip_long_timeline() {
+        let json = r#"{ "time": 1000000.001, "length": 0.001, "value": 1 }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let item = Item::from_json(&value, &[]).unwrap();
+
+        assert!(item.end() > 1000000.0015);
+        assert_eq!(item.to_json(&[]).unwrap(), value);
+    }
+
+    #[test]
+    fn item_round_trip_missing_curve() {
+        let json = r#"{ "time": 1, "length": 2, "curve": 5, "speed": 2 }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let item = Item::from_json(&value, &[]).unwrap();
+
+        assert!(item.curve.is_none());
+        assert_eq!(item.unresolved_curve, Some(5));
+        assert_eq!(item.get_value(1.5), 0.0);
+        assert_eq!(item.to_json(&[]).unwrap(), value);
+    }
+
+    #[test]
+    fn item_parse_invalid() {
+        let json = r#"{ "time": 1.0, "length": "long" }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let err = Item::from_json(&value, &[]).unwrap_err();
+
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "length"));
+    }
 }
@@ -0,0 +1,96 @@
+use serde_json::Value;
+
+use crate::error::SaveError;
+
+/// Converts a value for writing, the way the editor would print it.
+///
+/// Whole numbers are written without a fraction, and everything else uses the
+/// shortest decimal that reads back to the same `f32`, so saving a loaded
+/// project does not add noise digits to it. NaN and infinities have no JSON
+/// representation, so they fail with the given path.
+pub(crate) fn number(value: f32, path: &str) -> Result<Value, SaveError> {
+    if !value.is_finite() {
+        return Err(SaveError::non_finite(path, value.into()));
+    }
+
+    if value.fract() == 0.0 && value.abs() < 9007199254740992.0 {
+        return Ok(Value::from(value as i64));
+    }
+
+    let shortest = value.to_string().parse::<f64>().unwrap_or_default();
+    Ok(Value::from(shortest))
+}
+
+/// Like [`number`], for times.
+#[cfg(not(feature = "f64-time"))]
+pub(crate) fn time(value: crate::Time, path: &str) -> Result<Value, SaveError> {
+    number(value, path)
+}
+
+#[cfg(feature = "f64-time")]
+pub(crate) fn time(value: crate::Time, path: &str) -> Result<Value, SaveError> {
+    if !value.is_finite() {
+        return Err(SaveError::non_finite(path, value));
+    }
+
+    if value.fract() == 0.0 && value.abs() < 9007199254740992.0 {
+        return Ok(Value::from(value as i64));
+    }
+
+    Ok(Value::from(value))
+}
+
+/// Serializes a raw JSON value inside other serde formats.
+///
+/// Formats that can describe themselves get the value as is. The others, like
+/// MessagePack or bincode, can not deserialize a value without knowing its
+/// shape up front, so they get its JSON text instead.
+#[cfg(feature = "serde")]
+pub(crate) struct ValueRef<'a>(pub &'a Value);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ValueRef<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            self.0.serialize(serializer)
+        } else {
+            serializer.serialize_str(&self.0.to_string())
+        }
+    }
+}
+
+/// Counterpart of [`ValueRef`] for deserializing.
+#[cfg(feature = "serde")]
+pub(crate) struct OwnedValue(pub Value);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for OwnedValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            Value::deserialize(deserializer).map(Self)
+        } else {
+            let text = String::deserialize(deserializer)?;
+            serde_json::from_str(&text)
+                .map(Self)
+                .map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn number_formatting() {
+        let written = |value| number(value, "value").unwrap().to_string();
+        assert_eq!(written(0.0), "0");
+        assert_eq!(written(-3.0), "-3");
+        assert_eq!(written(0.1), "0.1");
+        assert_eq!(written(-2.35), "-2.35");
+
+        let err = number(f32::NAN, "value").unwrap_err();
+        assert!(matches!(err, SaveError::NonFinite { path, .. } if path == "value"));
+        assert!(time(crate::Time::INFINITY, "time").is_err());
+    }
+}
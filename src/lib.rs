@@ -2,59 +2,661 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
 mod bezier;
+mod compat;
+mod grid;
+mod json;
+mod order;
 
 pub mod channel;
 pub mod connection;
 pub mod curve;
+pub mod error;
 pub mod fx;
+pub mod group;
+pub mod gui;
 pub mod item;
+pub mod playback;
+pub mod query;
+pub mod reload;
 pub mod state;
+pub mod timeline;
+pub mod validate;
 
+use channel::{Channel, ChannelId};
 use connection::Connection;
+use curve::{Curve, Evaluation};
+use error::{LoadError, SaveError};
 use fx::FxFnBoxFn;
+use group::ChannelGroup;
+use playback::Playback;
+use reload::StatePublisher;
 use state::SaveState;
-use std::{collections::HashMap, fmt::Debug, io::Read, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, Read, Write},
+    sync::Arc,
+};
+use timeline::LoopMode;
+
+/// Type of all times and durations, in seconds.
+///
+/// Values stay `f32` either way, but times are `f64` with the `f64-time`
+/// feature, so long playback keeps sub-millisecond precision.
+#[cfg(not(feature = "f64-time"))]
+pub type Time = f32;
+#[cfg(feature = "f64-time")]
+pub type Time = f64;
 
-#[derive(Default)]
 pub struct Automaton {
-    time: f32,
-    state: Option<Arc<SaveState>>,
+    loop_mode: LoopMode,
+    playback: Option<Playback>,
     connection: Option<Connection>,
     fxs: HashMap<String, FxFnBoxFn>,
+    evaluation: Evaluation,
+    publisher: StatePublisher,
 }
 
 impl Debug for Automaton {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Automaton")
-            .field("time", &self.time)
-            .field("state", &self.state)
+            .field("loop_mode", &self.loop_mode)
+            .field("playback", &self.playback)
             .field("connection", &self.connection)
             .finish_non_exhaustive()
     }
 }
 
+impl Default for Automaton {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Automaton {
     pub fn new() -> Self {
         Self {
-            time: 0.0,
-            state: None,
+            loop_mode: LoopMode::default(),
+            playback: None,
             connection: None,
             fxs: HashMap::new(),
+            evaluation: Evaluation::default(),
+            publisher: StatePublisher::new(),
+        }
+    }
+
+    /// Loads a project and plays it from the start. A project published
+    /// before, but not picked up yet, is dropped.
+    pub fn load(&mut self, data: impl Read) -> Result<(), LoadError> {
+        let json = serde_json::from_reader(data)?;
+        let mut state = SaveState::from_json(json, &self.fxs)?;
+        state.set_evaluation(self.evaluation);
+        self.publisher.try_take();
+        self.playback = Some(self.playback_of(Arc::new(state)));
+        Ok(())
+    }
+
+    /// Returns a handle to publish new versions of the project from another
+    /// thread. They replace the loaded project at the start of the next
+    /// [`Automaton::update`], see [`Automaton::poll_state`].
+    ///
+    /// The publisher loads with the fx definitions added and the curve
+    /// evaluation set so far.
+    pub fn publisher(&self) -> StatePublisher {
+        self.publisher.with_settings(self.fxs.clone(), self.evaluation)
+    }
+
+    /// Swaps in the latest published project, if there is one, and returns
+    /// whether it did.
+    ///
+    /// Channels that keep their name carry over their playback position and
+    /// listeners; channels that are new start from scratch. Channel handles
+    /// may point to different channels afterwards, so they need to be looked
+    /// up again.
+    pub fn poll_state(&mut self) -> bool {
+        let state = match self.publisher.try_take() {
+            Some(state) => state,
+            None => return false,
+        };
+
+        match &mut self.playback {
+            Some(playback) => playback.swap_state(state),
+            None => self.playback = Some(self.playback_of(state)),
+        }
+        true
+    }
+
+    /// Writes the loaded project in the editor's JSON format, see
+    /// [`SaveState::to_json`].
+    pub fn save(&self, writer: impl Write) -> Result<(), SaveError> {
+        let state = self.state().ok_or(SaveError::NotLoaded)?;
+
+        serde_json::to_writer(writer, &state.to_json()?).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn state(&self) -> Option<&Arc<SaveState>> {
+        self.playback.as_ref().map(Playback::state)
+    }
+
+    /// Returns the current position on the timeline, after the loop mode has
+    /// been applied.
+    pub fn time(&self) -> Time {
+        self.playback.as_ref().map_or(0.0, Playback::time)
+    }
+
+    pub fn loop_mode(&self) -> LoopMode {
+        self.loop_mode
+    }
+
+    /// Changes how update times map onto the timeline. Takes effect with the
+    /// next [`Automaton::update`].
+    pub fn set_loop_mode(&mut self, mode: LoopMode) {
+        self.loop_mode = mode;
+        if let Some(playback) = &mut self.playback {
+            playback.set_loop_mode(mode);
+        }
+    }
+
+    /// Creates a separate set of playback cursors for the loaded project, e.g.
+    /// to evaluate it on another thread. It starts with the same loop mode.
+    pub fn playback(&self) -> Option<Playback> {
+        Some(self.playback_of(self.state()?.clone()))
+    }
+
+    fn playback_of(&self, state: Arc<SaveState>) -> Playback {
+        let mut playback = Playback::new(state);
+        playback.set_loop_mode(self.loop_mode);
+        playback
+    }
+
+    /// Looks up the handle of a channel by name, see [`SaveState::channel_id`].
+    pub fn channel_id(&self, name: &str) -> Option<ChannelId> {
+        self.state()?.channel_id(name)
+    }
+
+    /// Returns the playback state of the named channel.
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.playback.as_ref()?.channel(name)
+    }
+
+    pub fn channel_by_id(&self, id: ChannelId) -> Option<&Channel> {
+        self.playback.as_ref()?.channel_by_id(id)
+    }
+
+    /// Mutable access to a channel's playback state, e.g. to subscribe to it.
+    pub fn channel_mut(&mut self, name: &str) -> Option<&mut Channel> {
+        self.playback.as_mut()?.channel_mut(name)
+    }
+
+    pub fn channel_by_id_mut(&mut self, id: ChannelId) -> Option<&mut Channel> {
+        self.playback.as_mut()?.channel_by_id_mut(id)
+    }
+
+    /// Returns the value of the named channel as of the last
+    /// [`Automaton::update`], or 0 if there is no such channel.
+    pub fn auto(&self, name: &str) -> f32 {
+        self.playback.as_ref().map_or(0.0, |p| p.auto(name))
+    }
+
+    /// Same as [`Automaton::auto`], but without the name lookup.
+    pub fn auto_by_id(&self, id: ChannelId) -> f32 {
+        self.playback.as_ref().map_or(0.0, |p| p.auto_by_id(id))
+    }
+
+    /// Returns the values of all channels in the group as of the last
+    /// [`Automaton::update`].
+    pub fn auto_group<const N: usize>(&self, group: &ChannelGroup<N>) -> [f32; N] {
+        group.map(|id| self.channel_by_id(id), Channel::current_value)
+    }
+
+    /// Advances every channel to the given time, see [`Playback::update`].
+    ///
+    /// A newly published project is picked up before anything else.
+    pub fn update(&mut self, time: Time) {
+        self.poll_state();
+
+        if let Some(playback) = &mut self.playback {
+            playback.update(time);
         }
     }
 
-    pub fn load(&mut self, data: impl Read) {
-        let json = serde_json::from_reader(data).unwrap();
-        let state = SaveState::from_json(json, &self.fxs);
-        self.state = Some(Arc::new(state));
+    /// Moves back to the start and resets the playback state of every
+    /// channel, so the next update starts from scratch.
+    pub fn reset(&mut self) {
+        if let Some(playback) = &mut self.playback {
+            playback.reset();
+        }
+    }
+
+    /// Edits a curve of the loaded project while it plays, e.g. to tune it
+    /// live. `edit` also gets the fx definitions added so far, which the
+    /// edit methods of [`Curve`] need to apply fx sections again. The
+    /// channels pick up the change with the next [`Automaton::update`].
+    ///
+    /// Returns `None` if no project is loaded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no curve with this index.
+    pub fn edit_curve<R>(
+        &mut self,
+        index: usize,
+        edit: impl FnOnce(&mut Curve, &HashMap<String, FxFnBoxFn>) -> R,
+    ) -> Option<R> {
+        let fxs = &self.fxs;
+        let playback = self.playback.as_mut()?;
+        Some(playback.edit_curve(index, |curve| edit(curve, fxs)))
+    }
+
+    /// Moves the current time to the named label, and returns its time.
+    pub fn seek_to_label(&mut self, name: &str) -> Option<Time> {
+        let time = self.state()?.label(name)?.time;
+        self.update(time);
+        Some(time)
     }
 
     pub fn add_fx_definition(&mut self, name: String, fx: FxFnBoxFn) {
         self.fxs.insert(name, fx);
     }
+
+    /// Changes how the curves of projects loaded from now on are evaluated,
+    /// see [`Evaluation`]. Single curves can be changed on a
+    /// [`SaveState`] before publishing it, see
+    /// [`SaveState::set_curve_evaluation`].
+    pub fn set_evaluation(&mut self, evaluation: Evaluation) {
+        self.evaluation = evaluation;
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    // TODO
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    #[test]
+    fn thread_safety() {
+        fn assert_send<T: Send>() {}
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Automaton>();
+        assert_send_sync::<SaveState>();
+        assert_send_sync::<Playback>();
+        assert_send_sync::<StatePublisher>();
+        assert_send_sync::<Channel>();
+        assert_send_sync::<curve::Curve>();
+        assert_send_sync::<LoadError>();
+        assert_send_sync::<FxFnBoxFn>();
+        assert_send::<Box<dyn fx::FxFn>>();
+    }
+
+    #[test]
+    fn load_errors() {
+        let mut automaton = Automaton::new();
+
+        let err = automaton.load(&b"{ \"curves\": ["[..]).unwrap_err();
+        assert!(matches!(err, LoadError::Json(_)));
+
+        let err = automaton
+            .load(&br#"{ "curves": [{ "nodes": [] }] }"#[..])
+            .unwrap_err();
+        assert!(matches!(err, LoadError::Schema { path, .. } if path == "curves[0].nodes"));
+        assert!(automaton.state().is_none());
+
+        automaton.load(&b"{}"[..]).unwrap();
+        assert!(automaton.state().is_some());
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let json = r#"{
+            "version": "4.0.0",
+            "resolution": 100,
+            "curves": [{ "nodes": [[0, 0, 0, 0, 0.5], [1.5, 1, -0.5]], "fxs": [] }],
+            "channels": [["x", { "items": [{ "time": 0.25, "length": 1.5, "curve": 0 }] }]],
+            "labels": { "drop": 2 }
+        }"#;
+
+        let mut automaton = Automaton::new();
+        let err = automaton.save(Vec::new()).unwrap_err();
+        assert!(matches!(err, SaveError::NotLoaded));
+
+        automaton.load(json.as_bytes()).unwrap();
+
+        let mut saved = Vec::new();
+        automaton.save(&mut saved).unwrap();
+
+        let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+        let actual: serde_json::Value = serde_json::from_slice(&saved).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn seek_to_label() {
+        let mut automaton = Automaton::new();
+        assert_eq!(automaton.seek_to_label("drop"), None);
+
+        automaton
+            .load(&br#"{ "labels": { "drop": 12.5 } }"#[..])
+            .unwrap();
+
+        assert_eq!(automaton.seek_to_label("intro"), None);
+        assert_eq!(automaton.time(), 0.0);
+        assert_eq!(automaton.seek_to_label("drop"), Some(12.5));
+        assert_eq!(automaton.time(), 12.5);
+    }
+
+    #[test]
+    fn update_channels() {
+        let json = r#"{
+            "curves": [{ "nodes": [[0, 0], [2, 1]] }],
+            "channels": [
+                ["ramp", { "items": [{ "time": 0, "length": 2, "curve": 0 }] }]
+            ]
+        }"#;
+
+        let mut automaton = Automaton::new();
+        automaton.load(json.as_bytes()).unwrap();
+        assert_eq!(automaton.auto("ramp"), 0.0);
+
+        let state = automaton.state().unwrap().clone();
+        let ramp = state.channel("ramp").unwrap();
+
+        // forward playback
+        for &time in &[0.0, 0.5, 1.0, 1.5] {
+            automaton.update(time);
+            assert_eq!(automaton.time(), time);
+            assert_eq!(automaton.auto("ramp"), ramp.get_value(time));
+        }
+
+        // backward seek
+        automaton.update(0.25);
+        assert_eq!(automaton.auto("ramp"), ramp.get_value(0.25));
+        assert_eq!(automaton.channel("ramp").unwrap().current_time(), 0.25);
+
+        automaton.update(-1.0);
+        assert_eq!(automaton.time(), 0.0);
+
+        // the loaded state itself is never advanced
+        assert_eq!(ramp.current_time(), Time::NEG_INFINITY);
+
+        automaton.reset();
+        assert_eq!(automaton.auto("ramp"), 0.0);
+        assert_eq!(
+            automaton.channel("ramp").unwrap().current_time(),
+            Time::NEG_INFINITY
+        );
+    }
+
+    #[test]
+    fn loop_playback() {
+        let json = r#"{
+            "channels": [
+                ["flash", { "items": [
+                    { "time": 0.5, "length": 0.5, "value": 1 },
+                    { "time": 1.75, "length": 0.25, "value": 2, "reset": true }
+                ] }]
+            ],
+            "labels": { "start": 0, "end": 2 }
+        }"#;
+
+        let mut automaton = Automaton::new();
+        automaton.load(json.as_bytes()).unwrap();
+
+        let mode = LoopMode::between_labels(automaton.state().unwrap(), "start", "end");
+        automaton.set_loop_mode(mode.unwrap());
+
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = log.clone();
+        automaton
+            .channel_mut("flash")
+            .unwrap()
+            .subscribe(move |event| {
+                sink.lock()
+                    .unwrap()
+                    .push((event.begin, event.init, event.uninit))
+            });
+
+        let mut values = Vec::new();
+        for frame in 0..6 {
+            automaton.update(frame as Time * 0.7);
+            values.push((automaton.time(), automaton.auto("flash")));
+        }
+
+        // 0.0, 0.7, 1.4, 2.1, 2.8, 3.5 wrap to the region
+        let expected = [
+            (0.0, 0.0),
+            (0.7, 1.0),
+            (1.4, 1.0),
+            (0.1, 0.0),
+            (0.8, 1.0),
+            (1.5, 1.0),
+        ];
+        for ((time, value), (expected_time, expected_value)) in values.iter().zip(&expected) {
+            assert_approx_eq!(Time, *time, *expected_time, epsilon = 1e-5);
+            assert_eq!(value, expected_value);
+        }
+
+        // the item skipped over at the end of each pass still runs
+        let log = log.lock().unwrap();
+        assert_eq!(
+            *log,
+            [
+                (0.5, true, false),
+                (0.5, false, true),
+                (1.75, true, true),
+                (0.5, true, false),
+                (0.5, false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn ping_pong_playback() {
+        let json = r#"{
+            "curves": [{ "nodes": [[0, 0], [4, 4]] }],
+            "channels": [["ramp", { "items": [{ "time": 0, "length": 4, "curve": 0 }] }]]
+        }"#;
+
+        let mut automaton = Automaton::new();
+        automaton.load(json.as_bytes()).unwrap();
+        automaton.set_loop_mode(LoopMode::PingPong {
+            begin: 1.0,
+            end: 3.0,
+        });
+
+        let state = automaton.state().unwrap().clone();
+        let ramp = state.channel("ramp").unwrap();
+        for &(time, expected) in &[(0.5, 0.5), (2.5, 2.5), (3.5, 2.5), (4.5, 1.5), (5.5, 1.5)] {
+            automaton.update(time);
+            assert_eq!(automaton.time(), expected);
+            assert_eq!(automaton.auto("ramp"), ramp.get_value(expected));
+        }
+
+        // seeking back and resetting start over without wrapping
+        automaton.reset();
+        automaton.update(0.25);
+        assert_eq!(automaton.time(), 0.25);
+        automaton.set_loop_mode(LoopMode::Clamp { end: 2.0 });
+        automaton.update(10.0);
+        assert_eq!(automaton.time(), 2.0);
+    }
+
+    #[test]
+    fn exact_evaluation() {
+        let json = br#"{
+            "resolution": 10,
+            "curves": [{ "nodes": [[0, 0], [0.16, 1], [0.3, 0]] }],
+            "channels": [["spike", { "items": [{ "length": 0.3, "curve": 0 }] }]]
+        }"#;
+
+        let mut automaton = Automaton::new();
+        automaton.set_evaluation(Evaluation::Exact);
+        let publisher = automaton.publisher();
+
+        automaton.load(&json[..]).unwrap();
+        automaton.update(0.16);
+        assert_eq!(automaton.auto("spike"), 1.0);
+
+        publisher.load(&json[..]).unwrap();
+        automaton.update(0.16);
+        let curve = &automaton.state().unwrap().curves()[0];
+        assert_eq!(curve.evaluation(), Evaluation::Exact);
+        assert_eq!(automaton.auto("spike"), 1.0);
+    }
+
+    #[test]
+    fn hot_swap_state() {
+        let mut automaton = Automaton::new();
+        automaton
+            .load(&br#"{ "channels": [
+                ["kept", { "items": [{ "time": 1, "length": 2, "value": 1 }] }],
+                ["removed", { "items": [{ "time": 0, "length": 1, "value": 1 }] }]
+            ] }"#[..])
+            .unwrap();
+
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = log.clone();
+        automaton
+            .channel_mut("kept")
+            .unwrap()
+            .subscribe(move |event| sink.lock().unwrap().push((event.value, event.init)));
+
+        automaton.update(1.5);
+        assert!(!automaton.poll_state());
+
+        let publisher = automaton.publisher();
+        std::thread::spawn(move || {
+            publisher
+                .load(&br#"{ "channels": [
+                    ["added", { "items": [{ "time": 0, "length": 4, "value": 3 }] }],
+                    ["kept", { "items": [{ "time": 1, "length": 2, "value": 2 }] }]
+                ] }"#[..])
+                .unwrap();
+        })
+        .join()
+        .unwrap();
+
+        // nothing changes until the next update
+        assert_eq!(automaton.auto("kept"), 1.0);
+        assert!(automaton.channel("removed").is_some());
+
+        automaton.update(2.0);
+        assert!(automaton.channel("removed").is_none());
+        assert_eq!(automaton.auto("kept"), 2.0);
+        assert_eq!(automaton.auto("added"), 3.0);
+        assert_eq!(automaton.channel_id("kept").unwrap().index(), 1);
+
+        // the kept channel was already inside its item, so no second init
+        assert_eq!(*log.lock().unwrap(), [(1.0, true), (2.0, false)]);
+    }
+
+    #[test]
+    fn load_drops_published_state() {
+        let mut automaton = Automaton::new();
+        let publisher = automaton.publisher();
+
+        publisher
+            .load(&br#"{ "channels": [["published", {}]] }"#[..])
+            .unwrap();
+        automaton
+            .load(&br#"{ "channels": [["loaded", {}]] }"#[..])
+            .unwrap();
+
+        automaton.update(0.0);
+        assert!(automaton.channel("loaded").is_some());
+        assert!(automaton.channel("published").is_none());
+    }
+
+    #[test]
+    fn edit_curve_live() {
+        fn fx_offset() -> Box<dyn fx::FxFn> {
+            Box::new(|context: fx::FxContext| context.value + 1.0)
+        }
+
+        let json = r#"{
+            "curves": [{ "nodes": [[0, 0], [2, 1]] }],
+            "channels": [["ramp", { "items": [{ "time": 0, "length": 2, "curve": 0 }] }]]
+        }"#;
+
+        let mut automaton = Automaton::new();
+        assert_eq!(automaton.edit_curve(0, |_, _| ()), None);
+
+        automaton.add_fx_definition("offset".to_string(), fx_offset);
+        automaton.load(json.as_bytes()).unwrap();
+        let cursor = automaton.playback().unwrap();
+
+        automaton.update(1.0);
+        assert_eq!(automaton.auto("ramp"), 0.5);
+
+        let index = automaton.edit_curve(0, |curve, fxs| curve.move_node(1, 2.0, 3.0, fxs));
+        assert_eq!(index, Some(1));
+        automaton.update(1.0);
+        assert_eq!(automaton.auto("ramp"), 1.5);
+
+        let section = fx::FxSection {
+            time: 0.0,
+            length: 2.0,
+            row: 0,
+            def: "offset".to_string(),
+            params: Default::default(),
+            bypass: false,
+        };
+        automaton.edit_curve(0, |curve, fxs| curve.add_fx(section, fxs));
+        automaton.update(1.0);
+        assert_eq!(automaton.auto("ramp"), 2.5);
+
+        // the state follows the edits, other cursors keep the curve they had
+        let state = automaton.state().unwrap();
+        assert_eq!(state.curves()[0].nodes()[1].value, 3.0);
+        assert_eq!(state.channel("ramp").unwrap().get_value(1.0), 2.5);
+        assert_eq!(cursor.state().curves()[0].nodes()[1].value, 1.0);
+    }
+
+    #[test]
+    fn subscribe_channel() {
+        let json = r#"{
+            "channels": [["flash", { "items": [{ "time": 1, "length": 0.5, "value": 1 }] }]]
+        }"#;
+
+        let mut automaton = Automaton::new();
+        automaton.load(json.as_bytes()).unwrap();
+
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = log.clone();
+        automaton
+            .channel_mut("flash")
+            .unwrap()
+            .subscribe(move |event| sink.lock().unwrap().push((event.init, event.uninit)));
+
+        for frame in 0..4 {
+            automaton.update(frame as Time * 0.5);
+        }
+
+        assert_eq!(*log.lock().unwrap(), [(true, false), (false, true)]);
+    }
+
+    #[test]
+    fn named_channels() {
+        let json = r#"{
+            "channels": [
+                ["cameraX", { "items": [{ "time": 0, "length": 1, "value": 0.25 }] }],
+                ["cameraY", { "items": [{ "time": 0, "length": 1, "value": 0.5 }] }]
+            ]
+        }"#;
+
+        let mut automaton = Automaton::new();
+        assert!(automaton.channel("cameraX").is_none());
+        assert_eq!(automaton.auto("cameraX"), 0.0);
+
+        automaton.load(json.as_bytes()).unwrap();
+        automaton.update(0.0);
+        assert!(automaton.channel("cameraX").is_some());
+        assert!(automaton.channel("cameraZ").is_none());
+        assert_eq!(automaton.auto("cameraX"), 0.25);
+        assert_eq!(automaton.auto("cameraZ"), 0.0);
+
+        let id = automaton.channel_id("cameraY").unwrap();
+        assert_eq!(automaton.auto_by_id(id), 0.5);
+    }
 }
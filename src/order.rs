@@ -0,0 +1,107 @@
+use std::cmp::Ordering;
+
+/// Where the elements of a sorted list were in the project file.
+///
+/// Nodes, fx sections, items and labels are kept sorted for playback, but
+/// saving writes them back in the order of the file and diagnostics point at
+/// their position in it. Elements added later go right after the element
+/// before them, so sorted files stay sorted.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct FileOrder(Vec<usize>);
+
+impl FileOrder {
+    /// Sorts the elements stably, and remembers where each one came from.
+    pub(crate) fn sort<T: Clone>(
+        elements: &[T],
+        mut compare: impl FnMut(&T, &T) -> Ordering,
+    ) -> (Vec<T>, Self) {
+        let mut order = (0..elements.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| compare(&elements[a], &elements[b]));
+
+        let sorted = order.iter().map(|&i| elements[i].clone()).collect();
+        (sorted, Self(order))
+    }
+
+    /// Position in the file of the element with the given index.
+    pub(crate) fn position(&self, index: usize) -> usize {
+        self.0[index]
+    }
+
+    /// The elements in the order of the file, along with their positions.
+    pub(crate) fn in_file_order<'a, T>(
+        &self,
+        elements: &'a [T],
+    ) -> impl Iterator<Item = (usize, &'a T)> {
+        let mut written = self.0.iter().copied().zip(elements).collect::<Vec<_>>();
+        written.sort_by_key(|&(position, _)| position);
+        written.into_iter()
+    }
+
+    /// Makes room for an element inserted at the given index.
+    pub(crate) fn insert(&mut self, index: usize) {
+        let position = match index.checked_sub(1) {
+            Some(prev) => self.0[prev] + 1,
+            None => 0,
+        };
+
+        for p in &mut self.0 {
+            if *p >= position {
+                *p += 1;
+            }
+        }
+        self.0.insert(index, position);
+    }
+
+    /// Follows an element that moved from one index to another. It keeps its
+    /// position unless its neighbors change.
+    pub(crate) fn moved(&mut self, from: usize, to: usize) {
+        if from != to {
+            self.remove(from);
+            self.insert(to);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, index: usize) {
+        let position = self.0.remove(index);
+        for p in &mut self.0 {
+            if *p > position {
+                *p -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_order() {
+        let (sorted, mut order) = FileOrder::sort(&[3, 1, 2, 1], |a, b| a.cmp(b));
+        assert_eq!(sorted, [1, 1, 2, 3]);
+        assert_eq!(order.position(0), 1);
+        assert_eq!(order.position(3), 0);
+
+        let written = |order: &FileOrder, sorted: &[i32]| {
+            order
+                .in_file_order(sorted)
+                .map(|(_, &e)| e)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(written(&order, &sorted), [3, 1, 2, 1]);
+
+        // new elements follow the one before them
+        let mut sorted = sorted;
+        sorted.insert(3, 2);
+        order.insert(3);
+        assert_eq!(written(&order, &sorted), [3, 1, 2, 2, 1]);
+
+        sorted.insert(0, 0);
+        order.insert(0);
+        assert_eq!(written(&order, &sorted), [0, 3, 1, 2, 2, 1]);
+
+        sorted.remove(5);
+        order.remove(5);
+        assert_eq!(written(&order, &sorted), [0, 1, 2, 2, 1]);
+    }
+}
@@ -0,0 +1,287 @@
+use std::sync::Arc;
+
+use crate::{
+    channel::{Channel, ChannelId},
+    curve::Curve,
+    group::ChannelGroup,
+    state::SaveState,
+    timeline::LoopMode,
+    Time,
+};
+
+/// How many loop passes a single [`Playback::update`] plays at most. When a
+/// frame skips more than that, e.g. after a long hitch, the passes after
+/// these are skipped over at once.
+pub const MAX_REPLAYED_PASSES: i64 = 16;
+
+/// Playback cursors over a shared [`SaveState`].
+///
+/// The state itself is never modified during playback, so any number of
+/// threads can each own a `Playback` of the same state and evaluate it
+/// independently. An [`Automaton`](crate::Automaton) plays its project
+/// through one as well.
+#[derive(Debug, Clone)]
+pub struct Playback {
+    time: Time,
+    clock: Time,
+    loop_mode: LoopMode,
+    state: Arc<SaveState>,
+    channels: Vec<Channel>,
+}
+
+impl Playback {
+    pub fn new(state: Arc<SaveState>) -> Self {
+        Self {
+            time: 0.0,
+            clock: Time::NEG_INFINITY,
+            loop_mode: LoopMode::default(),
+            channels: state.channels().to_vec(),
+            state,
+        }
+    }
+
+    /// Swaps in another version of the project.
+    ///
+    /// Channels that keep their name carry over their playback position and
+    /// listeners; channels that are new start from scratch.
+    pub(crate) fn swap_state(&mut self, state: Arc<SaveState>) {
+        let mut channels = state.channels().to_vec();
+        for (channel, name) in channels.iter_mut().zip(state.channel_names()) {
+            let old = self
+                .state
+                .channel_id(name)
+                .and_then(|id| self.channels.get_mut(id.0));
+
+            if let Some(old) = old {
+                channel.resume_from(old);
+            }
+        }
+
+        self.channels = channels;
+        self.state = state;
+    }
+
+    /// Edits a curve of the played project, see [`SaveState::edit_curve`].
+    /// Other playbacks of the project keep the curve they had.
+    pub(crate) fn edit_curve<R>(&mut self, index: usize, edit: impl FnOnce(&mut Curve) -> R) -> R {
+        let old = self.state.curves().to_vec();
+        let state = Arc::make_mut(&mut self.state);
+        let result = state.edit_curve(index, edit);
+        for channel in &mut self.channels {
+            channel.replace_curves(&old, state.curves());
+        }
+
+        result
+    }
+
+    pub fn state(&self) -> &Arc<SaveState> {
+        &self.state
+    }
+
+    /// Returns the current position on the timeline, after the loop mode has
+    /// been applied.
+    pub fn time(&self) -> Time {
+        self.time
+    }
+
+    pub fn loop_mode(&self) -> LoopMode {
+        self.loop_mode
+    }
+
+    /// Changes how update times map onto the timeline. Takes effect with the
+    /// next [`Playback::update`].
+    pub fn set_loop_mode(&mut self, mode: LoopMode) {
+        self.loop_mode = mode;
+    }
+
+    /// Returns the playback state of the named channel.
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channel_by_id(self.state.channel_id(name)?)
+    }
+
+    pub fn channel_by_id(&self, id: ChannelId) -> Option<&Channel> {
+        self.channels.get(id.0)
+    }
+
+    /// Mutable access to a channel's playback state, e.g. to subscribe to it.
+    pub fn channel_mut(&mut self, name: &str) -> Option<&mut Channel> {
+        let id = self.state.channel_id(name)?;
+        self.channel_by_id_mut(id)
+    }
+
+    pub fn channel_by_id_mut(&mut self, id: ChannelId) -> Option<&mut Channel> {
+        self.channels.get_mut(id.0)
+    }
+
+    /// Returns the value of the named channel as of the last
+    /// [`Playback::update`], or 0 if there is no such channel.
+    pub fn auto(&self, name: &str) -> f32 {
+        self.channel(name).map_or(0.0, Channel::current_value)
+    }
+
+    /// Same as [`Playback::auto`], but without the name lookup.
+    pub fn auto_by_id(&self, id: ChannelId) -> f32 {
+        self.channel_by_id(id).map_or(0.0, Channel::current_value)
+    }
+
+    /// Returns the values of all channels in the group as of the last
+    /// [`Playback::update`].
+    pub fn auto_group<const N: usize>(&self, group: &ChannelGroup<N>) -> [f32; N] {
+        group.map(|id| self.channel_by_id(id), Channel::current_value)
+    }
+
+    /// Advances every channel to the given time and caches their values.
+    ///
+    /// The time is mapped onto the timeline by the [`LoopMode`], negative
+    /// times are clamped to 0. Seeking backward is fine, see
+    /// [`Channel::consume`]. When playing forward wraps around the loop
+    /// region, the channels first play up to the turning point, so items at
+    /// the end of the region still send their `uninit` events. Every pass
+    /// skipped over is played the same way, up to [`MAX_REPLAYED_PASSES`].
+    pub fn update(&mut self, time: Time) {
+        let prev_clock = self.clock;
+        self.clock = time;
+        self.time = self.loop_mode.apply(time);
+
+        if prev_clock < time {
+            let first = self.loop_mode.pass(prev_clock);
+            let skipped = self.loop_mode.pass(time) - first;
+            for pass in first..first + skipped.min(MAX_REPLAYED_PASSES) {
+                if pass > first {
+                    let start = self.loop_mode.pass_start(pass);
+                    for channel in &mut self.channels {
+                        channel.consume(start);
+                    }
+                }
+
+                let turning_point = self.loop_mode.turning_point(pass);
+                for channel in &mut self.channels {
+                    channel.consume(turning_point);
+                }
+            }
+        }
+
+        for channel in &mut self.channels {
+            channel.consume(self.time);
+        }
+    }
+
+    /// Moves back to the start and resets the playback state of every
+    /// channel, so the next update starts from scratch.
+    pub fn reset(&mut self) {
+        self.time = 0.0;
+        self.clock = Time::NEG_INFINITY;
+        for channel in &mut self.channels {
+            channel.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+    use std::{collections::HashMap, thread};
+
+    #[test]
+    fn playback_across_threads() {
+        let json = serde_json::json!({
+            "curves": [{ "nodes": [[0, 0], [4, 1]] }],
+            "channels": [["ramp", { "items": [{ "time": 0, "length": 4, "curve": 0 }] }]]
+        });
+
+        let state = Arc::new(SaveState::from_json(json, &HashMap::new()).unwrap());
+        let id = state.channel_id("ramp").unwrap();
+
+        let workers = (0..4)
+            .map(|worker| {
+                let mut playback = Playback::new(state.clone());
+                thread::spawn(move || {
+                    (0..100)
+                        .map(|frame| {
+                            let time = (worker * 100 + frame) as Time * 0.01;
+                            playback.update(time);
+                            (time, playback.auto_by_id(id))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let ramp = state.channel("ramp").unwrap();
+        for worker in workers {
+            for (time, value) in worker.join().unwrap() {
+                assert_eq!(value, ramp.get_value(time));
+            }
+        }
+    }
+
+    #[test]
+    fn playback_skipped_passes() {
+        let json = serde_json::json!({
+            "channels": [["flash", { "items": [{ "time": 0.5, "length": 0.2, "value": 1 }] }]]
+        });
+
+        let state = Arc::new(SaveState::from_json(json, &HashMap::new()).unwrap());
+        let mut playback = Playback::new(state);
+        playback.set_loop_mode(LoopMode::Loop {
+            begin: 0.0,
+            end: 1.0,
+        });
+
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = log.clone();
+        playback
+            .channel_mut("flash")
+            .unwrap()
+            .subscribe(move |event| sink.lock().unwrap().push((event.init, event.uninit)));
+
+        // every one of the five passes runs the item
+        playback.update(0.1);
+        playback.update(5.3);
+        assert_eq!(*log.lock().unwrap(), [(true, true); 5]);
+        assert_eq!(playback.auto("flash"), 0.0);
+
+        // a long hitch only replays so many passes
+        log.lock().unwrap().clear();
+        playback.update(105.3);
+        assert_eq!(log.lock().unwrap().len(), MAX_REPLAYED_PASSES as usize);
+        assert_approx_eq!(Time, playback.time(), 0.3, epsilon = 1e-3);
+    }
+
+    #[test]
+    fn playback_loop_and_reset() {
+        let json = serde_json::json!({
+            "channels": [["flash", { "items": [{ "time": 1.5, "length": 0.5, "value": 1 }] }]]
+        });
+
+        let state = Arc::new(SaveState::from_json(json, &HashMap::new()).unwrap());
+        let mut playback = Playback::new(state);
+        playback.set_loop_mode(LoopMode::Loop {
+            begin: 0.0,
+            end: 2.0,
+        });
+
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = log.clone();
+        playback
+            .channel_mut("flash")
+            .unwrap()
+            .subscribe(move |event| sink.lock().unwrap().push((event.init, event.uninit)));
+
+        playback.update(1.0);
+        playback.update(2.5);
+        assert_eq!(playback.time(), 0.5);
+        assert_eq!(playback.auto("flash"), 0.0);
+
+        // the item at the end of the region was passed on the way
+        assert_eq!(*log.lock().unwrap(), [(true, true)]);
+
+        playback.reset();
+        assert_eq!(playback.time(), 0.0);
+        assert_eq!(
+            playback.channel("flash").unwrap().current_time(),
+            Time::NEG_INFINITY
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Questions about the shape of curves and channels over a time interval.
+//!
+//! Curves are played back by interpolating linearly between their
+//! precalculated values, so the answers are worked out on that polyline
+//! directly. Bezier segments whose control points already rule out an answer
+//! are skipped as a whole.
+
+use crate::Time;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Rising,
+    Falling,
+}
+
+/// A point in time where the value passes a threshold.
+///
+/// A value rises through a threshold when it goes from below it to at least
+/// the threshold, and falls through it when it goes the other way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crossing {
+    pub time: Time,
+    pub direction: Direction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExtremumKind {
+    Minimum,
+    Maximum,
+}
+
+/// A local minimum or maximum. Flat extrema are reported where they begin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extremum {
+    pub time: Time,
+    pub value: f32,
+    pub kind: ExtremumKind,
+}
+
+/// Whether a bezier segment with these control values stays on one side of
+/// the threshold.
+pub(crate) fn hull_excludes(cps: [f32; 4], threshold: f32) -> bool {
+    cps.iter().all(|&c| c < threshold) || cps.iter().all(|&c| c >= threshold)
+}
+
+/// Whether a bezier segment with these control values never changes
+/// direction.
+pub(crate) fn monotonic(cps: [f32; 4]) -> bool {
+    cps.windows(2).all(|w| w[0] <= w[1]) || cps.windows(2).all(|w| w[0] >= w[1])
+}
+
+pub(crate) fn crossings(vertices: &[(Time, f32)], threshold: f32) -> Vec<Crossing> {
+    vertices
+        .windows(2)
+        .filter_map(|edge| {
+            let ((t0, v0), (t1, v1)) = (edge[0], edge[1]);
+            let direction = if v0 < threshold && v1 >= threshold {
+                Direction::Rising
+            } else if v0 >= threshold && v1 < threshold {
+                Direction::Falling
+            } else {
+                return None;
+            };
+
+            let time = t0 + (t1 - t0) * Time::from((threshold - v0) / (v1 - v0));
+            Some(Crossing { time, direction })
+        })
+        .collect()
+}
+
+pub(crate) fn extrema(vertices: &[(Time, f32)]) -> Vec<Extremum> {
+    let mut extrema = Vec::new();
+    let mut rising = None;
+    let mut turn = match vertices.first() {
+        Some(&vertex) => vertex,
+        None => return extrema,
+    };
+
+    for edge in vertices.windows(2) {
+        let (v0, v1) = (edge[0].1, edge[1].1);
+        if v0 == v1 {
+            continue;
+        }
+
+        if rising == Some(v0 > v1) {
+            extrema.push(Extremum {
+                time: turn.0,
+                value: turn.1,
+                kind: if v0 > v1 {
+                    ExtremumKind::Maximum
+                } else {
+                    ExtremumKind::Minimum
+                },
+            });
+        }
+
+        rising = Some(v0 < v1);
+        turn = edge[1];
+    }
+
+    extrema
+}
+
+pub(crate) fn value_range(vertices: &[(Time, f32)]) -> (f32, f32) {
+    vertices
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &(_, v)| {
+            (min.min(v), max.max(v))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polyline_queries() {
+        let vertices = [
+            (0.0, 0.0),
+            (1.0, 2.0),
+            (2.0, 2.0),
+            (3.0, 1.0),
+            (3.0, 3.0),
+            (4.0, 0.0),
+        ];
+
+        let times = |direction| {
+            crossings(&vertices, 1.5)
+                .into_iter()
+                .filter(|c| c.direction == direction)
+                .map(|c| c.time)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(Direction::Rising), [0.75, 3.0]);
+        assert_eq!(times(Direction::Falling), [2.5, 3.5]);
+
+        // the plateau counts from its beginning, the jump is a minimum
+        let found = extrema(&vertices)
+            .into_iter()
+            .map(|e| (e.time, e.value, e.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (1.0, 2.0, ExtremumKind::Maximum),
+                (3.0, 1.0, ExtremumKind::Minimum),
+                (3.0, 3.0, ExtremumKind::Maximum),
+            ]
+        );
+
+        assert_eq!(value_range(&vertices), (0.0, 3.0));
+    }
+
+    #[test]
+    fn control_point_checks() {
+        assert!(hull_excludes([0.0, 0.2, -1.0, 0.4], 0.5));
+        assert!(hull_excludes([0.5, 0.6, 0.7, 2.0], 0.5));
+        assert!(!hull_excludes([0.0, 0.6, 0.0, 0.0], 0.5));
+
+        assert!(monotonic([0.0, 0.0, 1.0, 2.0]));
+        assert!(monotonic([2.0, 1.0, 1.0, 0.0]));
+        assert!(!monotonic([0.0, 1.0, 0.5, 2.0]));
+    }
+}
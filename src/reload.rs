@@ -0,0 +1,74 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{Arc, Mutex, TryLockError},
+};
+
+use crate::{curve::Evaluation, error::LoadError, fx::FxFnBoxFn, state::SaveState};
+
+/// Hands new versions of a project to an [`Automaton`](crate::Automaton),
+/// e.g. from a thread that watches the project file.
+///
+/// Loading and precalculating the curves happens on the publishing thread.
+/// The automaton only swaps in the finished state at the start of its next
+/// update, and never waits for the publisher to do so.
+#[derive(Debug, Clone)]
+pub struct StatePublisher {
+    pending: Arc<Mutex<Option<Arc<SaveState>>>>,
+    fxs: HashMap<String, FxFnBoxFn>,
+    evaluation: Evaluation,
+}
+
+impl StatePublisher {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: Arc::default(),
+            fxs: HashMap::new(),
+            evaluation: Evaluation::default(),
+        }
+    }
+
+    /// Another publisher for the same automaton, loading with the given fx
+    /// definitions and curve evaluation.
+    pub(crate) fn with_settings(
+        &self,
+        fxs: HashMap<String, FxFnBoxFn>,
+        evaluation: Evaluation,
+    ) -> Self {
+        Self {
+            pending: self.pending.clone(),
+            fxs,
+            evaluation,
+        }
+    }
+
+    /// Loads a project with the fx definitions and curve evaluation the
+    /// automaton had when this publisher was created, and publishes it.
+    pub fn load(&self, data: impl Read) -> Result<(), LoadError> {
+        let json = serde_json::from_reader(data)?;
+        let mut state = SaveState::from_json(json, &self.fxs)?;
+        state.set_evaluation(self.evaluation);
+        self.publish(Arc::new(state));
+        Ok(())
+    }
+
+    /// Replaces any state that has not been picked up yet.
+    pub fn publish(&self, state: Arc<SaveState>) {
+        let mut pending = match self.pending.lock() {
+            Ok(pending) => pending,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        *pending = Some(state);
+    }
+
+    /// Takes the published state, unless there is none or the publisher is
+    /// busy storing one right now.
+    pub(crate) fn try_take(&self) -> Option<Arc<SaveState>> {
+        match self.pending.try_lock() {
+            Ok(mut pending) => pending.take(),
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().take(),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
@@ -1,72 +1,1030 @@
-use crate::channel::Channel;
-use crate::curve::Curve;
+use crate::channel::{Channel, ChannelId};
+use crate::compat;
+use crate::curve::{Curve, Evaluation};
+use crate::error::{self, LoadError, SaveError};
 use crate::fx::FxFnBoxFn;
+use crate::gui::{BeatGrid, GuiSettings};
+use crate::json;
+use crate::order::FileOrder;
+use crate::validate::{self, Diagnostic};
+use crate::Time;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 
-#[derive(Debug)]
+/// A loaded project.
+///
+/// With the `serde` feature the state serializes to a compact mirror of the
+/// editor format, in which items reference curves by index so shared curves
+/// stay shared.
+#[derive(Debug, Clone)]
 pub struct SaveState {
-    time: f32,
+    version: String,
     resolution: usize,
     curves: Vec<Arc<Curve>>,
     channels: Vec<Channel>,
+    channel_names: Vec<String>,
+    channel_ids: HashMap<String, ChannelId>,
+    /// Sorted by time, then name.
     labels: Vec<Label>,
+    label_order: FileOrder,
+    gui_settings: Option<GuiSettings>,
 }
 
 impl SaveState {
-    pub fn from_json(json: Value, fxs: &HashMap<String, FxFnBoxFn>) -> Self {
-        let resolution = json
-            .get("resolution")
-            .and_then(Value::as_u64)
-            .unwrap_or(100) as _;
-
-        let curves = json
-            .get("curves")
-            .and_then(Value::as_array)
+    /// Loads a project in the editor's JSON format.
+    ///
+    /// Curves that would need more than 2^24 precalculated values are
+    /// rejected, e.g. ones longer than 46 hours at a resolution of 100.
+    pub fn from_json(json: Value, fxs: &HashMap<String, FxFnBoxFn>) -> Result<Self, LoadError> {
+        let json = compat::migrate(json)?;
+
+        let version = error::field(&json, "version", Value::as_str, "a string")?
+            .unwrap_or(compat::CURRENT_VERSION_STRING)
+            .to_string();
+
+        let resolution = error::field_u64(&json, "resolution")?.unwrap_or(100) as _;
+        if resolution == 0 {
+            return Err(LoadError::schema(
+                "resolution",
+                "resolution must be positive",
+            ));
+        }
+
+        let curves = error::field_array(&json, "curves")?
             .into_iter()
             .flatten()
-            .map(|v| Arc::new(Curve::from_json(v, resolution, fxs)))
-            .collect::<Vec<_>>();
+            .enumerate()
+            .map(|(i, v)| {
+                Curve::from_json(v, resolution, fxs)
+                    .map(Arc::new)
+                    .map_err(|e| e.within(&format!("curves[{}]", i)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let channels = json
-            .get("channels")
-            .and_then(Value::as_array)
+        let mut channels = Vec::new();
+        let mut channel_names = Vec::new();
+        let mut channel_ids = HashMap::new();
+        for (i, v) in error::field_array(&json, "channels")?
             .into_iter()
             .flatten()
-            .map(|v| Channel::from_json(v, &curves))
-            .collect::<Vec<_>>();
+            .enumerate()
+        {
+            let path = format!("channels[{}]", i);
+            let (name, channel) =
+                Self::channel_from_json(v, &curves).map_err(|e| e.within(&path))?;
 
-        let labels = json
-            .get("labels")
-            .and_then(Value::as_object)
+            if channel_ids.insert(name.clone(), ChannelId(i)).is_some() {
+                return Err(LoadError::schema(
+                    format!("{}[0]", path),
+                    format!("duplicate channel name `{}`", name),
+                ));
+            }
+
+            channels.push(channel);
+            channel_names.push(name);
+        }
+
+        let labels = error::field(&json, "labels", Value::as_object, "an object")?
             .into_iter()
             .flatten()
-            .filter_map(|(name, value)| {
-                value
-                    .as_f64()
+            .map(|(name, value)| {
+                error::as_f64(value, &format!("labels.{}", name))
                     .map(|time| Label::new(name.clone(), time as _))
             })
-            .collect();
+            .collect::<Result<Vec<_>, _>>()?;
+        let (labels, label_order) = sort_labels(&labels);
+
+        let gui_settings = match json.get("guiSettings") {
+            None | Some(Value::Null) => None,
+            Some(v) => Some(GuiSettings::from_json(v).map_err(|e| e.within("guiSettings"))?),
+        };
 
-        Self {
-            time: 0.0,
+        Ok(Self {
+            version,
             resolution,
             curves,
             channels,
+            channel_names,
+            channel_ids,
             labels,
+            label_order,
+            gui_settings,
+        })
+    }
+
+    /// Checks the state for inconsistencies, like overlapping items or fx
+    /// sections past the end of their curve.
+    ///
+    /// All problems are reported, none of them stop the check.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut out = Vec::new();
+
+        for (i, curve) in self.curves.iter().enumerate() {
+            validate::check_curve(&mut out, &format!("curves[{}]", i), curve);
+        }
+
+        for (i, channel) in self.channels.iter().enumerate() {
+            let location = format!("channels[{}][1]", i);
+            validate::check_channel(&mut out, &location, channel, self.curves.len());
+        }
+
+        for label in &self.labels {
+            validate::check_label(&mut out, label);
+        }
+
+        if let Some(gui_settings) = &self.gui_settings {
+            validate::check_gui_settings(&mut out, gui_settings);
+        }
+
+        out
+    }
+
+    /// Writes the state in the editor's JSON format.
+    ///
+    /// Fails if a number is NaN or infinite, since the editor could not load
+    /// the file.
+    pub fn to_json(&self) -> Result<Value, SaveError> {
+        let curves = self
+            .curves
+            .iter()
+            .enumerate()
+            .map(|(i, curve)| {
+                curve
+                    .to_json()
+                    .map_err(|e| e.within(&format!("curves[{}]", i)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let channels = self
+            .channel_names
+            .iter()
+            .zip(&self.channels)
+            .enumerate()
+            .map(|(i, (name, channel))| {
+                let channel = channel
+                    .to_json(&self.curves)
+                    .map_err(|e| e.within(&format!("channels[{}][1]", i)))?;
+                Ok(serde_json::json!([name, channel]))
+            })
+            .collect::<Result<Vec<_>, SaveError>>()?;
+
+        let labels = self
+            .label_order
+            .in_file_order(&self.labels)
+            .map(|(_, label)| {
+                let time = json::time(label.time, &format!("labels.{}", label.name))?;
+                Ok((label.name.clone(), time))
+            })
+            .collect::<Result<_, SaveError>>()?;
+
+        let mut state = serde_json::json!({
+            "version": self.version,
+            "resolution": self.resolution,
+            "curves": Value::Array(curves),
+            "channels": Value::Array(channels),
+            "labels": Value::Object(labels),
+        });
+
+        if let Some(gui_settings) = &self.gui_settings {
+            state["guiSettings"] = gui_settings
+                .to_json()
+                .map_err(|e| e.within("guiSettings"))?;
+        }
+
+        Ok(state)
+    }
+
+    fn channel_from_json(
+        json: &Value,
+        curves: &[Arc<Curve>],
+    ) -> Result<(String, Channel), LoadError> {
+        let pair = error::as_array(json, "")?;
+        if pair.len() != 2 {
+            return Err(LoadError::schema("", "expected a `[name, channel]` pair"));
+        }
+
+        let name = pair[0]
+            .as_str()
+            .ok_or_else(|| LoadError::schema("[0]", "expected a string"))?;
+
+        let channel = Channel::from_json(&pair[1], curves).map_err(|e| e.within("[1]"))?;
+        Ok((name.to_string(), channel))
+    }
+
+    pub fn resolution(&self) -> usize {
+        self.resolution
+    }
+
+    pub fn curves(&self) -> &[Arc<Curve>] {
+        &self.curves
+    }
+
+    /// Changes how every curve is evaluated, see [`Evaluation`].
+    ///
+    /// Channels cloned from this state before keep the curves they had.
+    pub fn set_evaluation(&mut self, evaluation: Evaluation) {
+        self.update_curves(|_, curve| evaluated_as(curve, evaluation));
+    }
+
+    /// Changes how the curve with the given index is evaluated, see
+    /// [`SaveState::set_evaluation`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no curve with this index.
+    pub fn set_curve_evaluation(&mut self, index: usize, evaluation: Evaluation) {
+        assert!(index < self.curves.len(), "no curve with index {}", index);
+
+        self.update_curves(|i, curve| {
+            if i == index {
+                evaluated_as(curve, evaluation)
+            } else {
+                None
+            }
+        });
+    }
+
+    /// Edits the curve with the given index, e.g. with [`Curve::move_node`],
+    /// and points the items that use it to the edited curve.
+    ///
+    /// Channels cloned from this state before keep the curves they had.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no curve with this index.
+    pub fn edit_curve<R>(&mut self, index: usize, edit: impl FnOnce(&mut Curve) -> R) -> R {
+        assert!(index < self.curves.len(), "no curve with index {}", index);
+
+        let old = self.curves.clone();
+        let result = edit(Arc::make_mut(&mut self.curves[index]));
+        for channel in &mut self.channels {
+            channel.replace_curves(&old, &self.curves);
+        }
+
+        result
+    }
+
+    /// Replaces every curve for which `update` returns a new one, in the
+    /// curve list as well as in the items that use it.
+    fn update_curves(&mut self, mut update: impl FnMut(usize, &Curve) -> Option<Curve>) {
+        let old = self.curves.clone();
+        let mut changed = false;
+        for (i, curve) in self.curves.iter_mut().enumerate() {
+            if let Some(new) = update(i, curve) {
+                *curve = Arc::new(new);
+                changed = true;
+            }
         }
+
+        if changed {
+            for channel in &mut self.channels {
+                channel.replace_curves(&old, &self.curves);
+            }
+        }
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    /// Looks up the handle of the channel with the given name.
+    ///
+    /// The handle stays valid for the lifetime of this state and can be used
+    /// with [`SaveState::channel_by_id`] to skip the name lookup.
+    pub fn channel_id(&self, name: &str) -> Option<ChannelId> {
+        self.channel_ids.get(name).copied()
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channel_id(name).and_then(|id| self.channel_by_id(id))
+    }
+
+    pub fn channel_by_id(&self, id: ChannelId) -> Option<&Channel> {
+        self.channels.get(id.0)
+    }
+
+    pub fn channel_name(&self, id: ChannelId) -> Option<&str> {
+        self.channel_names.get(id.0).map(String::as_str)
+    }
+
+    /// Evaluates every channel at every given time.
+    ///
+    /// The output is channel-major: the samples of the channel with index `c`
+    /// end up in `out[c * times.len()..(c + 1) * times.len()]`, in the order
+    /// of `times`. Sorting the times ascending makes this fastest, see
+    /// [`Channel::sample_into`].
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `out` holds exactly one value per channel and time.
+    pub fn sample_into(&self, times: &[Time], out: &mut [f32]) {
+        assert_eq!(
+            out.len(),
+            times.len() * self.channels.len(),
+            "one output per channel and sample time"
+        );
+
+        if times.is_empty() {
+            return;
+        }
+
+        for (channel, out) in self.channels.iter().zip(out.chunks_exact_mut(times.len())) {
+            channel.sample_into(times, out);
+        }
+    }
+
+    /// Names of all channels, in the order the editor lists them.
+    pub fn channel_names(&self) -> impl Iterator<Item = &str> {
+        self.channel_names.iter().map(String::as_str)
+    }
+
+    /// Prefixes shared by more than one channel, in the order the editor
+    /// lists them, e.g. `camera.pos` for `camera.pos.x` and `camera.pos.y`.
+    pub fn group_prefixes(&self) -> Vec<&str> {
+        let prefixes = self
+            .channel_names()
+            .filter_map(|name| Some(name.rsplit_once('.')?.0))
+            .collect::<Vec<_>>();
+
+        let mut groups = Vec::new();
+        for (i, prefix) in prefixes.iter().enumerate() {
+            let shared = prefixes[i + 1..].contains(prefix);
+            if shared && !groups.contains(prefix) {
+                groups.push(*prefix);
+            }
+        }
+
+        groups
+    }
+
+    /// Editor settings, if the project was saved with any.
+    pub fn gui_settings(&self) -> Option<&GuiSettings> {
+        self.gui_settings.as_ref()
+    }
+
+    /// Beat grid set up in the editor, or the editor's default grid.
+    pub fn beat_grid(&self) -> BeatGrid {
+        self.gui_settings
+            .as_ref()
+            .map(GuiSettings::beat_grid)
+            .unwrap_or_default()
+    }
+
+    /// All labels, sorted by time.
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    pub fn label(&self, name: &str) -> Option<&Label> {
+        self.labels.iter().find(|label| label.name == name)
+    }
+
+    /// Returns the last label strictly before the given time.
+    pub fn prev_label(&self, time: Time) -> Option<&Label> {
+        let index = self.labels.partition_point(|label| label.time < time);
+        index.checked_sub(1).map(|index| &self.labels[index])
+    }
+
+    /// Returns the first label strictly after the given time.
+    pub fn next_label(&self, time: Time) -> Option<&Label> {
+        let index = self.labels.partition_point(|label| label.time <= time);
+        self.labels.get(index)
     }
 }
 
-#[derive(Debug)]
+/// A copy of the curve with the given evaluation, unless it already has it.
+fn evaluated_as(curve: &Curve, evaluation: Evaluation) -> Option<Curve> {
+    (curve.evaluation() != evaluation).then(|| {
+        let mut curve = curve.clone();
+        curve.set_evaluation(evaluation);
+        curve
+    })
+}
+
+/// Sorts labels by time, and labels at the same time by name, so the order
+/// does not depend on how the project file was written.
+fn sort_labels(labels: &[Label]) -> (Vec<Label>, FileOrder) {
+    FileOrder::sort(labels, |a, b| {
+        a.time
+            .partial_cmp(&b.time)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.name.cmp(&b.name))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Label {
     pub name: String,
-    pub time: f32,
+    pub time: Time,
 }
 
 impl Label {
-    pub fn new(name: String, time: f32) -> Self {
+    pub fn new(name: String, time: Time) -> Self {
         Self { name, time }
     }
 }
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(rename = "SaveState")]
+struct SaveStateRepr {
+    version: String,
+    resolution: usize,
+    curves: Vec<Arc<Curve>>,
+    channels: Vec<(String, Vec<ItemRepr>)>,
+    labels: Vec<Label>,
+    gui_settings: Option<json::OwnedValue>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "Item")]
+struct ItemRepr {
+    time: Time,
+    length: Time,
+    value: f32,
+    offset: Time,
+    speed: f32,
+    amp: f32,
+    reset: bool,
+    curve: Option<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SaveState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeStruct};
+
+        let channels = self
+            .channel_names
+            .iter()
+            .zip(&self.channels)
+            .map(|(name, channel)| {
+                let items = channel
+                    .items_in_file_order()
+                    .map(|(_, item)| ItemRepr {
+                        time: item.time,
+                        length: item.length,
+                        value: item.value,
+                        offset: item.offset,
+                        speed: item.speed,
+                        amp: item.amp,
+                        reset: item.reset,
+                        curve: item.curve_index(&self.curves),
+                    })
+                    .collect::<Vec<_>>();
+
+                (name, items)
+            })
+            .collect::<Vec<_>>();
+
+        // same fields as `SaveStateRepr`, written from borrowed data
+        let mut state = serializer.serialize_struct("SaveState", 6)?;
+        state.serialize_field("version", &self.version)?;
+        state.serialize_field("resolution", &self.resolution)?;
+        state.serialize_field("curves", &self.curves)?;
+        state.serialize_field("channels", &channels)?;
+        let labels = self.label_order.in_file_order(&self.labels);
+        state.serialize_field("labels", &labels.map(|(_, label)| label).collect::<Vec<_>>())?;
+        let gui_settings = self
+            .gui_settings
+            .as_ref()
+            .map(GuiSettings::to_json)
+            .transpose()
+            .map_err(S::Error::custom)?;
+        state.serialize_field("gui_settings", &gui_settings.as_ref().map(json::ValueRef))?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SaveState {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use crate::item::Item;
+        use serde::de::Error;
+
+        let SaveStateRepr {
+            version,
+            resolution,
+            curves,
+            channels: serialized_channels,
+            labels,
+            gui_settings,
+        } = SaveStateRepr::deserialize(deserializer)?;
+
+        let (labels, label_order) = sort_labels(&labels);
+
+        let mut channels = Vec::new();
+        let mut channel_names = Vec::new();
+        let mut channel_ids = HashMap::new();
+        for (i, (name, items)) in serialized_channels.into_iter().enumerate() {
+            if channel_ids.insert(name.clone(), ChannelId(i)).is_some() {
+                return Err(D::Error::custom(format!("duplicate channel name `{}`", name)));
+            }
+
+            let items = items
+                .into_iter()
+                .map(|item| Item {
+                    time: item.time,
+                    length: item.length,
+                    value: item.value,
+                    offset: item.offset,
+                    speed: item.speed,
+                    amp: item.amp,
+                    reset: item.reset,
+                    curve: item.curve.and_then(|index| curves.get(index).cloned()),
+                    unresolved_curve: item.curve.filter(|&index| index >= curves.len()),
+                })
+                .collect();
+
+            channels.push(Channel::new(items));
+            channel_names.push(name);
+        }
+
+        Ok(Self {
+            version,
+            resolution,
+            curves,
+            channels,
+            channel_names,
+            channel_ids,
+            labels,
+            label_order,
+            gui_settings: gui_settings
+                .map(|value| GuiSettings::from_json(&value.0))
+                .transpose()
+                .map_err(D::Error::custom)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::Severity;
+
+    fn schema_path(json: &str) -> String {
+        let value = serde_json::from_str(json).unwrap();
+        match SaveState::from_json(value, &HashMap::new()).unwrap_err() {
+            LoadError::Schema { path, .. } => path,
+            other => panic!("expected a schema error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn state_parse_simple() {
+        let json = r#"{
+            "resolution": 50,
+            "curves": [{ "nodes": [[0, 0], [1, 1]] }],
+            "channels": [["x", { "items": [{ "time": 0, "length": 1, "curve": 0 }] }]],
+            "labels": { "drop": 4.5 }
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value, &HashMap::new()).unwrap();
+
+        assert_eq!(state.resolution(), 50);
+        assert_eq!(state.curves().len(), 1);
+        assert_eq!(state.channels().len(), 1);
+        assert!(state.channel("x").is_some());
+        assert_eq!(state.labels()[0].name, "drop");
+        assert_eq!(state.labels()[0].time, 4.5);
+    }
+
+    #[test]
+    fn state_parse_v3() {
+        let json = r#"{
+            "version": "3.0.0",
+            "curves": [{ "nodes": [{ "time": 0 }, { "time": 2, "value": 1 }] }],
+            "channels": { "x": { "items": [{ "time": 1, "length": 2, "curve": 0 }] } }
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value, &HashMap::new()).unwrap();
+
+        assert_eq!(state.curves()[0].length(), 2.0);
+        assert_eq!(state.channels().len(), 1);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let json = r#"{
+            "version": "4.1.0",
+            "resolution": 50,
+            "curves": [
+                {
+                    "nodes": [[0, 0, 0, 0, 0.3, 0.1], [1.2, 1, -0.4, -0.1]],
+                    "fxs": [
+                        { "def": "missing", "params": { "a": [1, 2] }, "time": 0.2, "length": 0.5, "row": 0 },
+                        { "def": "missing", "params": {}, "time": 0.1, "length": 0.5, "row": 1, "bypass": true }
+                    ]
+                },
+                { "nodes": [[0, 3], [2.5, -1]], "fxs": [] }
+            ],
+            "channels": [
+                ["b", { "items": [{ "time": 0.1, "length": 2.5, "curve": 1, "speed": 2, "amp": -1 }] }],
+                ["a", { "items": [
+                    { "time": 0, "length": 1.2, "curve": 0, "offset": 0.7 },
+                    { "time": 3, "length": 0, "value": 0.5, "reset": true }
+                ] }]
+            ],
+            "labels": { "outro": 64, "intro": 0, "drop": 16.25 },
+            "guiSettings": {
+                "snapTimeActive": true,
+                "snapTimeInterval": 0.05,
+                "snapValueActive": false,
+                "snapValueInterval": 0.1,
+                "snapBeatActive": true,
+                "bpm": 174,
+                "beatOffset": 0.125,
+                "useBeatInGUI": true,
+                "minimizedPrecisionTime": 3,
+                "minimizedPrecisionValue": 3
+            }
+        }"#;
+
+        let value: Value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value.clone(), &HashMap::new()).unwrap();
+        assert_eq!(state.to_json().unwrap(), value);
+
+        let reloaded = SaveState::from_json(state.to_json().unwrap(), &HashMap::new()).unwrap();
+        assert_eq!(reloaded.to_json().unwrap(), value);
+
+        // objects compare equal in any order, so check the labels as written
+        let labels = reloaded.to_json().unwrap()["labels"].to_string();
+        assert_eq!(labels, r#"{"outro":64,"intro":0,"drop":16.25}"#);
+    }
+
+    #[test]
+    fn state_round_trip_as_written() {
+        // unsorted nodes and sections, and an item whose curve is missing
+        let json = r#"{
+            "version": "4.1.0",
+            "resolution": 100,
+            "curves": [
+                {
+                    "nodes": [[1, 1], [0, 0], [0.5, 2]],
+                    "fxs": [
+                        { "def": "a", "params": {}, "time": 1, "length": 0.5, "row": 0 },
+                        { "def": "b", "params": {}, "time": 0.2, "length": 0.5, "row": 0 },
+                        { "def": "c", "params": {}, "time": 0, "length": 0.5, "row": 1 }
+                    ]
+                }
+            ],
+            "channels": [
+                ["x", { "items": [{ "time": 0, "length": 1, "curve": 5, "speed": 2 }] }]
+            ],
+            "labels": {}
+        }"#;
+
+        let value: Value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value.clone(), &HashMap::new()).unwrap();
+
+        // played back sorted, saved as written
+        let curve = &state.curves()[0];
+        assert_eq!(curve.get_value(0.5), 2.0);
+        assert_eq!(curve.fxs()[0].def, "b");
+        assert_eq!(state.to_json().unwrap(), value);
+        assert!(state.channels()[0].items()[0].curve.is_none());
+
+        let reloaded = SaveState::from_json(state.to_json().unwrap(), &HashMap::new()).unwrap();
+        assert_eq!(reloaded.to_json().unwrap(), value);
+    }
+
+    #[test]
+    fn state_save_non_finite() {
+        let json = r#"{ "curves": [{ "nodes": [[0, 0], [1, 1]] }] }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let mut state = SaveState::from_json(value, &HashMap::new()).unwrap();
+
+        state.edit_curve(0, |curve| curve.move_node(1, 1.0, f32::NAN, &HashMap::new()));
+
+        // instead of writing `null`, which the editor could not load
+        match state.to_json().unwrap_err() {
+            SaveError::NonFinite { path, value } => {
+                assert_eq!(path, "curves[0].nodes[1][1]");
+                assert!(value.is_nan());
+            }
+            other => panic!("expected a non-finite number, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn state_serde_round_trip() {
+        let json = r#"{
+            "resolution": 20,
+            "curves": [
+                { "nodes": [[0, 0, 0, 0, 0.3, 0.1], [1.2, 1, -0.4, -0.1]] },
+                { "nodes": [[2.5, -1], [0, 3]] }
+            ],
+            "channels": [
+                ["b", { "items": [
+                    { "time": 0.1, "length": 2.5, "curve": 1, "speed": 2 },
+                    { "time": 3, "curve": 7 }
+                ] }],
+                ["a", { "items": [{ "length": 1.2, "curve": 0 }, { "time": 3, "curve": 0 }] }]
+            ],
+            "labels": { "outro": 64, "drop": 16.25 },
+            "guiSettings": { "bpm": 140 }
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value, &HashMap::new()).unwrap();
+
+        let check = |actual: SaveState| {
+            assert_eq!(actual.to_json().unwrap(), state.to_json().unwrap());
+            assert_eq!(actual.to_json().unwrap()["curves"][1]["nodes"][0][0], 2.5);
+            assert_eq!(actual.channel("b").unwrap().items()[1].unresolved_curve, Some(7));
+            assert_eq!(actual.curves()[0].resolution(), 20);
+
+            let labels = actual.to_json().unwrap()["labels"].to_string();
+            assert_eq!(labels, r#"{"outro":64,"drop":16.25}"#);
+
+            // items referencing the same curve still share it
+            let items = actual.channel("a").unwrap().items();
+            let (a, b) = (items[0].curve.as_ref(), items[1].curve.as_ref());
+            assert!(Arc::ptr_eq(a.unwrap(), b.unwrap()));
+        };
+
+        let bytes = rmp_serde::to_vec(&state).unwrap();
+        check(rmp_serde::from_slice(&bytes).unwrap());
+
+        let text = ron::to_string(&state).unwrap();
+        check(ron::from_str(&text).unwrap());
+
+        let text = serde_json::to_string(&state).unwrap();
+        check(serde_json::from_str(&text).unwrap());
+    }
+
+    #[test]
+    fn state_beat_grid() {
+        let value = serde_json::from_str("{}").unwrap();
+        let state = SaveState::from_json(value, &HashMap::new()).unwrap();
+        assert!(state.gui_settings().is_none());
+        assert_eq!(state.beat_grid().bpm, 140.0);
+
+        let json = r#"{ "guiSettings": { "bpm": 120, "beatOffset": 0.5 } }"#;
+        let value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value, &HashMap::new()).unwrap();
+        assert_eq!(state.gui_settings().unwrap().bpm, 120.0);
+        assert_eq!(state.beat_grid().beat_to_time(2.0), 1.5);
+
+        assert_eq!(
+            schema_path(r#"{ "guiSettings": { "beatOffset": true } }"#),
+            "guiSettings.beatOffset"
+        );
+    }
+
+    #[test]
+    fn state_labels() {
+        let json = r#"{
+            "labels": { "outro": 60, "intro": 0, "drop": 32, "build": 16, "break": 32 }
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value, &HashMap::new()).unwrap();
+
+        let names = state.labels().iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["intro", "build", "break", "drop", "outro"]);
+
+        assert_eq!(state.label("drop").unwrap().time, 32.0);
+        assert!(state.label("verse").is_none());
+
+        fn name(label: Option<&Label>) -> Option<&str> {
+            label.map(|l| l.name.as_str())
+        }
+
+        assert_eq!(name(state.prev_label(0.0)), None);
+        assert_eq!(name(state.prev_label(16.0)), Some("intro"));
+        assert_eq!(name(state.prev_label(16.5)), Some("build"));
+        assert_eq!(name(state.prev_label(100.0)), Some("outro"));
+        assert_eq!(name(state.next_label(-1.0)), Some("intro"));
+        assert_eq!(name(state.next_label(0.0)), Some("build"));
+        assert_eq!(name(state.next_label(20.0)), Some("break"));
+        assert_eq!(name(state.next_label(60.0)), None);
+    }
+
+    #[test]
+    fn state_validate() {
+        let json = r#"{
+            "curves": [
+                {
+                    "nodes": [[0, 0], [1, 1]],
+                    "fxs": [
+                        { "def": "a", "time": 0.5, "length": 1 },
+                        { "def": "b", "time": 2, "length": 1 },
+                        { "def": "c", "time": 0, "length": -1 }
+                    ]
+                },
+                { "nodes": [[1, 1], [0.5, 0]] }
+            ],
+            "channels": [
+                ["ok", { "items": [{ "time": 0, "length": 1 }, { "time": 1, "length": 1 }] }],
+                ["bad", { "items": [
+                    { "time": 0, "length": 2, "curve": 3 },
+                    { "time": 1, "length": -1 }
+                ] }],
+                ["unsorted", { "items": [{ "time": 2, "length": 1 }, { "time": 0, "length": 3 }] }],
+                ["nested", { "items": [
+                    { "time": 0, "length": 10 },
+                    { "time": 1, "length": 1 },
+                    { "time": 3, "length": 1 }
+                ] }]
+            ],
+            "labels": { "intro": -1 }
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value, &HashMap::new()).unwrap();
+
+        let mut actual = state
+            .validate()
+            .into_iter()
+            .map(|d| (d.severity, d.location))
+            .collect::<Vec<_>>();
+        actual.sort();
+
+        let mut expected = vec![
+            // locations are positions in the file, not in sorted order
+            (Severity::Warning, "curves[0].fxs[0]"),
+            (Severity::Warning, "curves[0].fxs[1]"),
+            (Severity::Error, "curves[0].fxs[2]"),
+            (Severity::Warning, "curves[1].nodes[1]"),
+            (Severity::Error, "channels[1][1].items[0].curve"),
+            (Severity::Error, "channels[1][1].items[1]"),
+            (Severity::Warning, "channels[1][1].items[1]"),
+            (Severity::Warning, "channels[2][1].items[0]"),
+            // both overlap the first item, not just the one before them
+            (Severity::Warning, "channels[3][1].items[1]"),
+            (Severity::Warning, "channels[3][1].items[2]"),
+            (Severity::Warning, "labels.intro"),
+        ]
+        .into_iter()
+        .map(|(severity, location)| (severity, location.to_string()))
+        .collect::<Vec<_>>();
+        expected.sort();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn state_validate_nan() {
+        let json = r#"{ "curves": [{ "nodes": [[0, 0], [1, 1]] }] }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let mut state = SaveState::from_json(value, &HashMap::new()).unwrap();
+        assert!(state.validate().is_empty());
+
+        state.edit_curve(0, |curve| curve.move_node(1, 1.0, f32::NAN, &HashMap::new()));
+        let diagnostics = state.validate();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].location, "curves[0].nodes[1].value");
+    }
+
+    #[test]
+    fn state_channel_lookup() {
+        let json = r#"{
+            "channels": [
+                ["cameraX", { "items": [{ "value": 1 }] }],
+                ["cameraY", { "items": [{ "value": 2 }, { "time": 1 }] }]
+            ]
+        }"#;
+
+        let value = serde_json::from_str(json).unwrap();
+        let state = SaveState::from_json(value, &HashMap::new()).unwrap();
+
+        let id = state.channel_id("cameraY").unwrap();
+        assert_eq!(state.channel_name(id), Some("cameraY"));
+        assert_eq!(state.channel_by_id(id).unwrap().items().len(), 2);
+        assert_eq!(state.channel("cameraX").unwrap().items().len(), 1);
+        assert!(state.channel("cameraZ").is_none());
+        assert_eq!(
+            state.channel_names().collect::<Vec<_>>(),
+            ["cameraX", "cameraY"]
+        );
+    }
+
+    #[test]
+    fn state_parse_schema_errors() {
+        assert_eq!(schema_path("[]"), "");
+        assert_eq!(schema_path(r#"{ "resolution": -1 }"#), "resolution");
+        assert_eq!(schema_path(r#"{ "curves": {} }"#), "curves");
+        assert_eq!(
+            schema_path(r#"{ "curves": [{ "nodes": [[0], [1]] }, { "nodes": [[0]] }] }"#),
+            "curves[1].nodes"
+        );
+        assert_eq!(
+            schema_path(r#"{ "curves": [{ "nodes": [[0], [1, null]] }] }"#),
+            "curves[0].nodes[1][1]"
+        );
+        assert_eq!(
+            schema_path(r#"{ "channels": [["x", { "items": [{}, { "reset": 1 }] }]] }"#),
+            "channels[0][1].items[1].reset"
+        );
+        assert_eq!(schema_path(r#"{ "channels": [[0, {}]] }"#), "channels[0][0]");
+        assert_eq!(
+            schema_path(r#"{ "channels": [["a", {}], ["a", {}]] }"#),
+            "channels[1][0]"
+        );
+        assert_eq!(
+            schema_path(r#"{ "labels": { "intro": "0" } }"#),
+            "labels.intro"
+        );
+    }
+
+    #[test]
+    fn state_parse_too_long() {
+        // instead of overflowing or running out of memory
+        for json in [
+            r#"{ "curves": [{ "nodes": [[0, 0], [1e30, 1]] }] }"#,
+            r#"{ "curves": [{ "nodes": [[0, 0], [1e9, 1]] }] }"#,
+            r#"{ "resolution": 1000000000000, "curves": [{ "nodes": [[0, 0], [1, 1]] }] }"#,
+        ] {
+            assert_eq!(schema_path(json), "curves[0].nodes[1]");
+        }
+
+        let json = r#"{
+            "curves": [{ "nodes": [[0, 0], [1, 1]] }, { "nodes": [[1e9, 1], [0, 0]] }]
+        }"#;
+        assert_eq!(schema_path(json), "curves[1].nodes[0]");
+    }
+
+    #[test]
+    fn state_sample_into() {
+        let json = r#"{
+            "curves": [{ "nodes": [[0, 0], [1, 1]] }],
+            "channels": [
+                ["a", { "items": [{ "time": 0, "length": 1, "curve": 0 }] }],
+                ["b", { "items": [{ "time": 0.5, "length": 1, "value": 2 }] }],
+                ["c", { "items": [] }]
+            ]
+        }"#;
+
+        let state = SaveState::from_json(serde_json::from_str(json).unwrap(), &HashMap::new())
+            .unwrap();
+
+        let times = [0.0, 0.25, 0.75, 2.0];
+        let mut out = [f32::NAN; 12];
+        state.sample_into(&times, &mut out);
+
+        for (c, channel) in state.channels().iter().enumerate() {
+            for (i, &time) in times.iter().enumerate() {
+                assert_eq!(out[c * times.len() + i], channel.get_value(time));
+            }
+        }
+        assert_eq!(out[4..8], [0.0, 0.0, 2.0, 2.0]);
+
+        let mut empty = [];
+        state.sample_into(&[], &mut empty);
+    }
+
+    #[test]
+    fn state_curve_evaluation() {
+        let json = r#"{
+            "resolution": 10,
+            "curves": [
+                { "nodes": [[0, 0], [0.15, 1], [0.3, 0]] },
+                { "nodes": [[0, 0], [1, 1]] }
+            ],
+            "channels": [
+                ["a", { "items": [{ "length": 0.3, "curve": 0 }, { "time": 1, "curve": 0 }] }],
+                ["b", { "items": [{ "length": 1, "curve": 1 }] }]
+            ]
+        }"#;
+
+        let mut state = SaveState::from_json(serde_json::from_str(json).unwrap(), &HashMap::new())
+            .unwrap();
+        let a = state.channel("a").unwrap().clone();
+        let b = state.curves()[1].clone();
+
+        state.set_curve_evaluation(0, Evaluation::Exact);
+        assert_eq!(state.curves()[0].evaluation(), Evaluation::Exact);
+        assert_eq!(state.curves()[1].evaluation(), Evaluation::Precalc);
+        assert_eq!(state.channel("a").unwrap().get_value(0.15), 1.0);
+
+        // items follow the new curve, untouched curves stay shared
+        let items = state.channel("a").unwrap().items();
+        for item in items {
+            assert!(Arc::ptr_eq(item.curve.as_ref().unwrap(), &state.curves()[0]));
+        }
+        assert!(Arc::ptr_eq(&state.curves()[1], &b));
+
+        // clones taken before keep the old curve
+        let curve = a.items()[0].curve.as_ref().unwrap();
+        assert_eq!(curve.evaluation(), Evaluation::Precalc);
+
+        state.set_evaluation(Evaluation::Exact);
+        assert!(state
+            .curves()
+            .iter()
+            .all(|curve| curve.evaluation() == Evaluation::Exact));
+        let item = &state.channel("b").unwrap().items()[0];
+        assert!(Arc::ptr_eq(item.curve.as_ref().unwrap(), &state.curves()[1]));
+    }
+}
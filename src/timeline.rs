@@ -0,0 +1,175 @@
+use crate::{state::SaveState, Time};
+
+/// How [`Automaton::update`](crate::Automaton::update) maps the time it is
+/// given onto the timeline.
+///
+/// Looping modes play the timeline normally until the end of their region,
+/// and only then start to wrap around. Regions that are empty or not finite
+/// do not loop at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopMode {
+    /// Plays once and stops at `end`.
+    Clamp { end: Time },
+    /// Jumps back to `begin` whenever `end` is reached. Loop a whole demo of
+    /// length `d` with `begin: 0.0, end: d`.
+    Loop { begin: Time, end: Time },
+    /// Plays the region forward and backward in turns.
+    PingPong { begin: Time, end: Time },
+}
+
+impl LoopMode {
+    /// Loops the region between two labels of the given state.
+    pub fn between_labels(state: &SaveState, begin: &str, end: &str) -> Option<Self> {
+        Some(Self::Loop {
+            begin: state.label(begin)?.time,
+            end: state.label(end)?.time,
+        })
+    }
+
+    fn region(&self) -> Option<(Time, Time)> {
+        match *self {
+            Self::Clamp { .. } => None,
+            Self::Loop { begin, end } | Self::PingPong { begin, end } => {
+                let valid = begin.is_finite() && end.is_finite() && begin < end;
+                valid.then(|| (begin.max(0.0), end))
+            }
+        }
+    }
+
+    /// Returns how many times the playback has reached a turning point by
+    /// the given time.
+    pub(crate) fn pass(&self, time: Time) -> i64 {
+        match self.region() {
+            Some((begin, end)) if time >= end => ((time - begin) / (end - begin)).floor() as i64,
+            _ => 0,
+        }
+    }
+
+    /// Returns the timeline time at which the given pass ends.
+    pub(crate) fn turning_point(&self, pass: i64) -> Time {
+        match (*self, self.region()) {
+            (Self::PingPong { .. }, Some((begin, _))) if pass % 2 == 1 => begin,
+            (_, Some((_, end))) => end,
+            _ => Time::INFINITY,
+        }
+    }
+
+    /// Returns the timeline time at which the given pass starts, for every
+    /// pass after the first.
+    pub(crate) fn pass_start(&self, pass: i64) -> Time {
+        match (*self, self.region()) {
+            (Self::PingPong { .. }, Some((_, end))) if pass % 2 == 1 => end,
+            (_, Some((begin, _))) => begin,
+            _ => 0.0,
+        }
+    }
+
+    /// Maps a playback time to the timeline.
+    pub fn apply(&self, time: Time) -> Time {
+        let time = time.max(0.0);
+
+        if let Self::Clamp { end } = *self {
+            return time.min(end.max(0.0));
+        }
+
+        let (begin, end) = match self.region() {
+            Some(region) if time >= region.1 => region,
+            _ => return time,
+        };
+
+        let length = end - begin;
+        let phase = (time - begin).rem_euclid(length);
+        match self {
+            Self::PingPong { .. } if self.pass(time) % 2 == 1 => end - phase,
+            _ => begin + phase,
+        }
+    }
+}
+
+impl Default for LoopMode {
+    fn default() -> Self {
+        Self::Clamp { end: Time::INFINITY }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    #[test]
+    fn loop_mode_apply() {
+        let clamp = LoopMode::Clamp { end: 4.0 };
+        assert_eq!(clamp.apply(-1.0), 0.0);
+        assert_eq!(clamp.apply(2.5), 2.5);
+        assert_eq!(clamp.apply(5.0), 4.0);
+        assert_eq!(LoopMode::default().apply(1e6), 1e6);
+
+        let repeat = LoopMode::Loop {
+            begin: 1.0,
+            end: 3.0,
+        };
+        assert_approx_eq!(Time, repeat.apply(0.5), 0.5);
+        assert_approx_eq!(Time, repeat.apply(2.5), 2.5);
+        assert_approx_eq!(Time, repeat.apply(3.0), 1.0);
+        assert_approx_eq!(Time, repeat.apply(3.5), 1.5);
+        assert_approx_eq!(Time, repeat.apply(6.25), 2.25);
+
+        let ping_pong = LoopMode::PingPong {
+            begin: 1.0,
+            end: 3.0,
+        };
+        assert_approx_eq!(Time, ping_pong.apply(2.5), 2.5);
+        assert_approx_eq!(Time, ping_pong.apply(3.5), 2.5);
+        assert_approx_eq!(Time, ping_pong.apply(4.5), 1.5);
+        assert_approx_eq!(Time, ping_pong.apply(5.5), 1.5);
+        assert_approx_eq!(Time, ping_pong.apply(6.5), 2.5);
+
+        // empty regions do not loop
+        let empty = LoopMode::Loop {
+            begin: 2.0,
+            end: 2.0,
+        };
+        assert_eq!(empty.apply(5.0), 5.0);
+    }
+
+    #[test]
+    fn loop_mode_turning_points() {
+        let ping_pong = LoopMode::PingPong {
+            begin: 1.0,
+            end: 3.0,
+        };
+
+        let passes = [2.0, 3.0, 4.5, 5.0, 7.5].map(|time| ping_pong.pass(time));
+        assert_eq!(passes, [0, 1, 1, 2, 3]);
+        assert_eq!(ping_pong.turning_point(0), 3.0);
+        assert_eq!(ping_pong.turning_point(1), 1.0);
+        assert_eq!(ping_pong.turning_point(2), 3.0);
+
+        let repeat = LoopMode::Loop {
+            begin: 1.0,
+            end: 3.0,
+        };
+        assert_eq!(repeat.turning_point(0), 3.0);
+        assert_eq!(repeat.turning_point(1), 3.0);
+        assert_eq!(repeat.pass_start(1), 1.0);
+        assert_eq!(repeat.pass_start(2), 1.0);
+        assert_eq!(ping_pong.pass_start(1), 3.0);
+        assert_eq!(ping_pong.pass_start(2), 1.0);
+    }
+
+    #[test]
+    fn loop_between_labels() {
+        let json = serde_json::json!({ "labels": { "verse": 4, "chorus": 12 } });
+        let state = SaveState::from_json(json, &Default::default()).unwrap();
+
+        assert_eq!(
+            LoopMode::between_labels(&state, "verse", "chorus"),
+            Some(LoopMode::Loop {
+                begin: 4.0,
+                end: 12.0
+            })
+        );
+        assert_eq!(LoopMode::between_labels(&state, "verse", "bridge"), None);
+    }
+}
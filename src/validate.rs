@@ -0,0 +1,230 @@
+//! Consistency checks for loaded projects.
+//!
+//! Loading only rejects data it can not make sense of. Projects that load fine
+//! can still contain things the editor would never produce, which
+//! [`SaveState::validate`](crate::state::SaveState::validate) reports all at
+//! once instead of stopping at the first one.
+
+use std::fmt;
+
+use crate::{channel::Channel, curve::Curve, gui::GuiSettings, item::Item, state::Label, Time};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The project plays, but probably not the way it was meant to.
+    Warning,
+    /// Part of the project is broken and will not play back correctly.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// JSON path of the offending value, e.g. `channels[2][1].items[4]`.
+    pub location: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn warning(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn error(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        write!(f, "{} at `{}`: {}", severity, self.location, self.message)
+    }
+}
+
+/// Checks fields of one type, either [`Time`](crate::Time)s or values.
+fn check_finite<T>(out: &mut Vec<Diagnostic>, location: &str, fields: &[(&str, T)])
+where
+    T: Copy + Into<f64> + fmt::Display,
+{
+    for (name, value) in fields {
+        if !(*value).into().is_finite() {
+            out.push(Diagnostic::error(
+                format!("{}.{}", location, name),
+                format!("{} is {}", name, value),
+            ));
+        }
+    }
+}
+
+pub(crate) fn check_curve(out: &mut Vec<Diagnostic>, location: &str, curve: &Curve) {
+    // curves sort what they load, diagnostics point into the file
+    for (i, node) in curve.nodes().iter().enumerate() {
+        let location = format!("{}.nodes[{}]", location, curve.node_position(i));
+        check_finite(
+            out,
+            &location,
+            &[
+                ("time", node.time),
+                ("inTime", node.in_time),
+                ("outTime", node.out_time),
+            ],
+        );
+        check_finite(
+            out,
+            &location,
+            &[
+                ("value", node.value),
+                ("inValue", node.in_value),
+                ("outValue", node.out_value),
+            ],
+        );
+    }
+
+    if let Some(first) = curve.nodes().first() {
+        if first.time != 0.0 {
+            out.push(Diagnostic::warning(
+                format!("{}.nodes[{}]", location, curve.node_position(0)),
+                format!("first node is at {} instead of 0", first.time),
+            ));
+        }
+    }
+
+    let length = curve.length();
+    for (i, fx) in curve.fxs().iter().enumerate() {
+        let location = format!("{}.fxs[{}]", location, curve.fx_position(i));
+        check_finite(out, &location, &[("time", fx.time), ("length", fx.length)]);
+
+        if fx.length < 0.0 {
+            out.push(Diagnostic::error(
+                &location,
+                format!("fx section `{}` has negative length {}", fx.def, fx.length),
+            ));
+        } else if fx.time >= length {
+            out.push(Diagnostic::warning(
+                &location,
+                format!(
+                    "fx section `{}` starts at {}, after the curve ends at {}",
+                    fx.def, fx.time, length
+                ),
+            ));
+        } else if fx.time + fx.length > length {
+            out.push(Diagnostic::warning(
+                &location,
+                format!(
+                    "fx section `{}` ends at {}, after the curve ends at {}",
+                    fx.def,
+                    fx.time + fx.length,
+                    length
+                ),
+            ));
+        }
+    }
+}
+
+fn check_item(out: &mut Vec<Diagnostic>, location: &str, item: &Item, curves: usize) {
+    check_finite(
+        out,
+        location,
+        &[
+            ("time", item.time),
+            ("length", item.length),
+            ("offset", item.offset),
+        ],
+    );
+    check_finite(
+        out,
+        location,
+        &[
+            ("value", item.value),
+            ("speed", item.speed),
+            ("amp", item.amp),
+        ],
+    );
+
+    if item.length < 0.0 {
+        out.push(Diagnostic::error(
+            location,
+            format!("item has negative length {}", item.length),
+        ));
+    }
+
+    // loading drops curves that do not exist, the item plays without one
+    if let Some(index) = item.unresolved_curve {
+        out.push(Diagnostic::error(
+            format!("{}.curve", location),
+            format!(
+                "item references curve {}, but there are only {} curves",
+                index, curves
+            ),
+        ));
+    }
+}
+
+pub(crate) fn check_channel(
+    out: &mut Vec<Diagnostic>,
+    location: &str,
+    channel: &Channel,
+    curves: usize,
+) {
+    // items are sorted on load, each one is checked against the item before
+    // it that ends last
+    let mut latest: Option<(usize, Time)> = None;
+    for (i, item) in channel.items().iter().enumerate() {
+        let position = channel.item_position(i);
+        let location = format!("{}.items[{}]", location, position);
+        check_item(out, &location, item, curves);
+
+        if let Some((latest_position, latest_end)) = latest {
+            if item.time < latest_end {
+                out.push(Diagnostic::warning(
+                    &location,
+                    format!(
+                        "item starts at {}, overlapping items[{}] which ends at {}",
+                        item.time, latest_position, latest_end
+                    ),
+                ));
+            }
+        }
+
+        if latest.is_none_or(|(_, end)| item.end() > end) {
+            latest = Some((position, item.end()));
+        }
+    }
+}
+
+pub(crate) fn check_label(out: &mut Vec<Diagnostic>, label: &Label) {
+    let location = format!("labels.{}", label.name);
+
+    if !label.time.is_finite() {
+        out.push(Diagnostic::error(location, format!("time is {}", label.time)));
+    } else if label.time < 0.0 {
+        out.push(Diagnostic::warning(
+            location,
+            format!("label is at negative time {}", label.time),
+        ));
+    }
+}
+
+pub(crate) fn check_gui_settings(out: &mut Vec<Diagnostic>, settings: &GuiSettings) {
+    if !(settings.bpm.is_finite() && settings.bpm > 0.0) {
+        out.push(Diagnostic::error(
+            "guiSettings.bpm",
+            format!("bpm must be positive, got {}", settings.bpm),
+        ));
+    }
+
+    check_finite(out, "guiSettings", &[("beatOffset", settings.beat_offset)]);
+}